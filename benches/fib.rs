@@ -0,0 +1,37 @@
+// Times a recursive fib with a growing number of global definitions. Since every
+// function call only adds a small scope on top of the one the function was
+// created in, the time per run should stay flat as the globals grow.
+//
+// Run with: cargo bench --bench fib
+use std::time::Instant;
+
+use lisper::env::{ LisperEnv, create_default_env };
+use lisper::core::{ tokenize, parse_program, eval_program };
+
+const FIB: &str = "(fn fib n (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))";
+const RUNS: u32 = 10;
+
+fn evaluate(source: &str, env: &mut LisperEnv) -> String {
+    let tokens = tokenize(source.to_string());
+    let program = parse_program(&tokens).expect("Unable to parse benchmark source");
+    let results = eval_program(program, env).expect("Unable to evaluate benchmark source");
+    results.last().map(|res| res.to_string()).unwrap_or_default()
+}
+
+fn main() {
+    for globals in [0, 1_000, 10_000, 100_000].iter() {
+        let env = &mut create_default_env();
+        for i in 0..*globals {
+            env.define(format!("global-{}", i), lisper::exp::LisperExp::Number(i as f64));
+        }
+        evaluate(FIB, env);
+
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            assert_eq!(evaluate("(fib 15)", env), "610");
+        }
+        let per_run = start.elapsed() / RUNS;
+
+        println!("fib 15 with {:>6} globals: {:?} per run", globals, per_run);
+    }
+}
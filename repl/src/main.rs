@@ -4,28 +4,58 @@ use std::io::Write;
 use lisper::env::{ LisperEnv, create_default_env };
 use lisper::core::{ 
     tokenize,
-    parse,
-    eval,
+    parse_program,
+    eval_program,
+    set_stack_size,
+    Token,
     LisperErr
 };
 // Get package version defined in cargo.toml
 const PKG_VERSION:&str = env!("CARGO_PKG_VERSION");
+// The stack size of the thread the REPL runs on
+const REPL_STACK_SIZE:usize = 256 * 1024 * 1024;
 
-fn evaluate(exp:String, env: &mut LisperEnv) -> Result<String, LisperErr> {
-    let tokens:Vec<String> = tokenize(exp);
-    println!("tokens: {:?}", tokens);
-    let (parsed_tokens, _) = parse(&tokens)?;
-    println!("parsed tokens: {}", parsed_tokens);
-    let eval_out = eval(parsed_tokens, env)?;
-    println!("eval: {}", eval_out);
+// Evaluates every expression read from the REPL. Spans are shifted to be relative to
+// the whole session, so errors in functions defined on earlier lines still point
+// at the right source.
+fn evaluate(exp:String, session: &str, env: &mut LisperEnv) -> Result<Vec<String>, LisperErr> {
+    let offset = session.len() - exp.len();
+    let lines = session[..offset].matches('\n').count();
+    let mut tokens:Vec<Token> = tokenize(exp);
+    for token in tokens.iter_mut() {
+        token.span.start += offset;
+        token.span.end += offset;
+        token.span.line += lines;
+    }
+    println!("tokens: {:?}", tokens.iter().map(|t| t.text.as_str()).collect::<Vec<&str>>());
+    let program = parse_program(&tokens)?;
+    for (parsed_tokens, _) in program.iter() {
+        println!("parsed tokens: {}", parsed_tokens);
+    }
+    let eval_out = eval_program(program, env)?;
 
-    Ok(eval_out.to_string())
+    Ok(eval_out.iter().map(|res| res.to_string()).collect())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>>{
+    // Recursion that isn't in tail position uses stack, so the REPL runs on a thread
+    // with a lot more of it than the main thread gets
+    let repl = std::thread::Builder::new().stack_size(REPL_STACK_SIZE).spawn(|| {
+        set_stack_size(REPL_STACK_SIZE);
+        repl()
+    })?;
+    repl.join().map_err(|_| "the REPL thread panicked")?;
+
+    Ok(())
+}
+
+fn repl() {
     // Create lisper environment
     let env = &mut create_default_env();
-    
+
+    // Everything entered so far, used to show where errors happened
+    let mut session = String::new();
+
     // Welcome message, including current version
     println!("Lisper v{}", PKG_VERSION);
 
@@ -51,11 +81,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>>{
             },
             _ => {
                 // Evaluate the string as a lisper expression
-                match evaluate(expr, env) {
-                    Ok(res) => println!("{}", res),
-                    Err(e) => match e {
-                    LisperErr::Reason(msg) => println!("Error = {}", msg),
+                if !session.is_empty() {
+                    session.push('\n');
+                }
+                session.push_str(&expr);
+                match evaluate(expr, &session, env) {
+                    Ok(results) => {
+                        for res in results {
+                            println!("{}", res);
+                        }
                     },
+                    Err(e) => println!("{}", e.render(&session)),
                 }
             }
         }
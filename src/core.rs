@@ -1,494 +1,2955 @@
 use std::fmt;
 use std::error;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
 
-use crate::exp::LisperExp;
-use crate::env::LisperEnv;
+use crate::exp::{ map_entries, LisperExp, LisperLambda, LisperParams, Span };
+use crate::env::{ is_equal, LisperEnv };
+use crate::num::{ BigInt, Num, Ratio };
 // An error type for the Lisp interperter
 #[derive(Debug)]
 pub enum LisperErr {
-    Reason(String)
+    // Code that isn't valid Lisper, found while parsing or by a special form
+    Syntax(String),
+    // A name that isn't bound in any scope
+    UnboundSymbol(String),
+    // A value of the wrong type, e.g. calling something that isn't a function
+    Type { expected: String, found: String },
+    // A call with the wrong number of arguments, expected is e.g. "2" or "at least 1"
+    Arity { name: String, expected: String, got: usize },
+    DivisionByZero,
+    // An error raised by Lisper code, carrying any value
+    User(LisperExp),
+    // Running out of something, such as stack for nested calls
+    ResourceLimit(String),
+    // An index past the end of a list or vector
+    IndexOutOfRange(String),
+    // Any other error
+    Reason(String),
+    // An error tied to the range of source code that caused it
+    Located(Box<LisperErr>, Span),
+    // An error along with the names of the functions being called when it happened,
+    // innermost first
+    Trace(Box<LisperErr>, Vec<String>),
 }
 
-impl error::Error for LisperErr {}
+impl LisperErr {
+    // Creates a type error from the value that was found
+    pub fn type_error(expected: &str, found: &LisperExp) -> LisperErr {
+        LisperErr::Type { expected: expected.to_string(), found: found.to_string() }
+    }
+
+    // Creates an arity error for a function taking from min to max arguments, or at least min
+    pub fn arity(name: &str, min: usize, max: Option<usize>, got: usize) -> LisperErr {
+        let expected = match max {
+            None => format!("at least {}", min),
+            Some(max) if max == min => min.to_string(),
+            Some(max) => format!("{} to {}", min, max)
+        };
+        LisperErr::Arity { name: name.to_string(), expected, got }
+    }
+
+    // Returns the error itself, without the location and call stack around it
+    pub fn kind(&self) -> &LisperErr {
+        match self {
+            LisperErr::Located(err, _) | LisperErr::Trace(err, _) => err.kind(),
+            _ => self,
+        }
+    }
+
+    // Attaches a source span to the error, unless it already points somewhere more specific
+    pub fn with_span(self, span: Span) -> LisperErr {
+        match self {
+            LisperErr::Located(_, _) => self,
+            LisperErr::Trace(err, stack) => LisperErr::Trace(Box::new(err.with_span(span)), stack),
+            _ => LisperErr::Located(Box::new(self), span),
+        }
+    }
+
+    // Returns the source span the error points at, if known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            LisperErr::Located(_, span) => Some(*span),
+            LisperErr::Trace(err, _) => err.span(),
+            _ => None,
+        }
+    }
+
+    // Adds a function the error passed through on its way out to the call stack
+    pub fn in_function(self, name: &str) -> LisperErr {
+        match self {
+            LisperErr::Trace(err, mut stack) => {
+                stack.push(name.to_string());
+                LisperErr::Trace(err, stack)
+            },
+            _ => LisperErr::Trace(Box::new(self), vec![name.to_string()]),
+        }
+    }
+
+    // Names the kind of error, as used by catch clauses, e.g. (catch :type e ...)
+    pub fn kind_name(&self) -> &'static str {
+        match self.kind() {
+            LisperErr::Syntax(_) => "syntax",
+            LisperErr::UnboundSymbol(_) => "unbound-symbol",
+            LisperErr::Type { .. } => "type",
+            LisperErr::Arity { .. } => "arity",
+            LisperErr::DivisionByZero => "division-by-zero",
+            LisperErr::User(_) => "user",
+            LisperErr::ResourceLimit(_) => "resource-limit",
+            LisperErr::IndexOutOfRange(_) => "index-out-of-range",
+            _ => "error",
+        }
+    }
+
+    // Returns the value a catch clause binds for the error. Errors raised by Lisper code
+    // give back the value they were raised with, and anything else gives its message.
+    pub fn value(&self) -> LisperExp {
+        match self.kind() {
+            LisperErr::User(value) => value.clone(),
+            err => LisperExp::Str(err.to_string()),
+        }
+    }
+
+    // Returns the names of the functions being called when the error happened, innermost first
+    pub fn stack(&self) -> &[String] {
+        match self {
+            LisperErr::Trace(_, stack) => stack,
+            _ => &[],
+        }
+    }
+
+    // Renders the error message followed by the failing line of source with the
+    // offending range underlined, and then the call stack, e.g.
+    //   Error = Unbound variable `foo`. (line 2, column 12)
+    //   2 | (fn bar x (foo x))
+    //     |           ^^^^^^^
+    //       at bar
+    pub fn render(&self, source: &str) -> String {
+        let (located, stack) = match self {
+            LisperErr::Trace(err, stack) => (err.as_ref(), stack.as_slice()),
+            _ => (self, &[] as &[String]),
+        };
+        let trace = render_stack(stack);
+        let message = format!("Error = {}", located);
+        let span = match self.span() {
+            Some(span) if span.start <= source.len() && source.is_char_boundary(span.start) => span,
+            _ => return format!("{}{}", message, trace),
+        };
+
+        // Find the full line the span starts on
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.start..].find('\n').map_or(source.len(), |i| span.start + i);
+        let underline_end = if span.end > span.start && span.end <= line_end && source.is_char_boundary(span.end) {
+            span.end
+        } else {
+            line_end
+        };
+
+        // Keep tabs in the padding so the carets line up with the source above
+        let padding: String = source[line_start..span.start].chars()
+                                                             .map(|c| if c == '\t' { '\t' } else { ' ' })
+                                                             .collect();
+        let carets = "^".repeat(source[span.start..underline_end].chars().count().max(1));
+        let gutter = " ".repeat(span.line.to_string().len());
+
+        format!("{}\n{} | {}\n{} | {}{}{}", message, span.line, &source[line_start..line_end], gutter, padding, carets, trace)
+    }
+}
+
+impl error::Error for LisperErr {
+    // The location and call stack wrap the error they describe
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            LisperErr::Located(err, _) | LisperErr::Trace(err, _) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for LisperErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            LisperErr::Syntax(reason) => write!(f, "Syntax error, {}", reason),
+            LisperErr::UnboundSymbol(name) => write!(f, "Unbound variable `{}`.", name),
+            LisperErr::Type { expected, found } => write!(f, "Type error, expected {}, found {}.", expected, found),
+            LisperErr::Arity { name, expected, got } => write!(f, "Arity error, {} expects {} arguments, got {}.", name, expected, got),
+            LisperErr::DivisionByZero => write!(f, "Division by zero."),
+            // Strings are shown as the message they are, rather than as a literal
+            LisperErr::User(LisperExp::Str(message)) => write!(f, "Error, {}", message),
+            LisperErr::User(value) => write!(f, "Error, {}", value),
+            LisperErr::ResourceLimit(reason) => write!(f, "Resource limit reached, {}.", reason),
+            LisperErr::IndexOutOfRange(reason) => write!(f, "Index error, {}.", reason),
             LisperErr::Reason(reason) => write!(f, "{}", reason),
+            LisperErr::Located(err, span) => write!(f, "{} (line {}, column {})", err, span.line, span.col),
+            LisperErr::Trace(err, stack) => write!(f, "{}{}", err, render_stack(stack)),
         }
     }
 }
 
-// Breaks an input string into separate one character tokens
-// TODO: Handle )( without the spaces
-pub fn tokenize(expr: String) -> Vec<String> {
-    expr
-        .replace("(", "( ")
-        .replace(")", " )")
-        .split_whitespace()
-        .map(|x| x.to_string())
-        .collect()
+// Lists the functions in a call stack one per line, collapsing runs of recursive calls
+fn render_stack(stack: &[String]) -> String {
+    let mut lines = String::new();
+    let mut i = 0;
+    while i < stack.len() {
+        let repeats = stack[i..].iter().take_while(|name| **name == stack[i]).count();
+        lines.push_str(&format!("\n    at {}", stack[i]));
+        if repeats > 1 {
+            lines.push_str(&format!(" ({} times)", repeats));
+        }
+        i += repeats;
+    }
+    lines
 }
 
-// Parses an array of string tokens and creates corresponding LisperExp objects
-pub fn parse(tokens: &[String]) -> Result<(LisperExp, &[String]), LisperErr> {
-    let (first, rest) = tokens.split_first()
-        .ok_or_else(|| LisperErr::Reason("Could not get token".to_string()))?;
+// A single token read from the source, along with where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub span: Span,
+}
 
-    let mut parsed_result: Vec<LisperExp> = vec![];
+impl PartialEq<&str> for Token {
+    fn eq(&self, other: &&str) -> bool {
+        self.text == *other
+    }
+}
 
-    match first.as_str() {
-        "(" => {
-            let mut more = rest;
-            loop {
-                let (next, more_next) = more.split_first()
-                    .ok_or_else(|| 
-                        LisperErr::Reason("Error reading token, missing ).".to_string())
-                    )?;
-                if next == ")" {
-                    return Ok((LisperExp::List(parsed_result), more_next))
+// Breaks an input string into tokens, splitting on whitespace and parentheses,
+// and records the line, column and byte offsets of each token. String literals
+// are kept whole, quotes and escapes included, and decoded by the parser.
+// Line comments (;) and nestable block comments (#| ... |#) are dropped here,
+// while datum comments (#;) and the quote prefixes (' ` , ,@) are passed on to
+// the parser as tokens.
+pub fn tokenize(expr: String) -> Vec<Token> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut current: Option<Token> = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut in_line_comment = false;
+    // Nesting depth of block comments, and where the outermost one started
+    let mut block_depth = 0;
+    let mut block_start = Span::default();
+    let mut line = 1;
+    let mut col = 1;
+    let mut chars = expr.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        let span = Span { start: offset, end: offset + c.len_utf8(), line, col };
+        // Comment markers are two characters long, #| |# and #;
+        let next = chars.peek().map(|(_, next)| *next);
+        let mut skip_next = false;
+
+        if in_string {
+            // Inside a string literal everything up to the closing quote is kept
+            if let Some(token) = current.as_mut() {
+                token.text.push(c);
+                token.span.end = span.end;
+            }
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+                tokens.extend(current.take());
+            }
+        } else if in_line_comment {
+            in_line_comment = c != '\n';
+        } else if block_depth > 0 {
+            if c == '|' && next == Some('#') {
+                block_depth -= 1;
+                skip_next = true;
+            } else if c == '#' && next == Some('|') {
+                block_depth += 1;
+                skip_next = true;
+            }
+        } else if c == ';' || c == '#' && (next == Some('|') || next == Some(';')) {
+            // Comments also end the token we were reading
+            tokens.extend(current.take());
+            if c == ';' {
+                in_line_comment = true;
+            } else if next == Some('|') {
+                block_depth = 1;
+                block_start = Span { end: offset + 2, ..span };
+                skip_next = true;
+            } else {
+                tokens.push(Token { text: "#;".to_string(), span: Span { end: offset + 2, ..span } });
+                skip_next = true;
+            }
+        } else if c == '\'' || c == '`' || c == ',' {
+            // Quote prefixes, including the two character ,@
+            tokens.extend(current.take());
+            if c == ',' && next == Some('@') {
+                tokens.push(Token { text: ",@".to_string(), span: Span { end: offset + 2, ..span } });
+                skip_next = true;
+            } else {
+                tokens.push(Token { text: c.to_string(), span });
+            }
+        } else if c.is_whitespace() || "(){}[]\"".contains(c) {
+            // Finish the token we were reading
+            tokens.extend(current.take());
+            if !c.is_whitespace() {
+                let token = Token { text: c.to_string(), span };
+                if c == '"' {
+                    in_string = true;
+                    current = Some(token);
+                } else {
+                    tokens.push(token);
+                }
+            }
+        } else {
+            match current.as_mut() {
+                Some(token) => {
+                    token.text.push(c);
+                    token.span.end = span.end;
+                },
+                None => {
+                    current = Some(Token { text: c.to_string(), span });
                 }
-                let (exp, new_more) = parse(&more)?;
-                parsed_result.push(exp);
-                more = new_more;
             }
+        }
+
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+        if skip_next {
+            chars.next();
+            col += 1;
+        }
+    }
+
+    tokens.extend(current);
+
+    // Leave a marker for an unclosed block comment so the parser can report it
+    if block_depth > 0 {
+        tokens.push(Token { text: "#|".to_string(), span: block_start });
+    }
+
+    tokens
+}
+
+thread_local! {
+    // The stack address of the outermost parse or eval call that is running
+    static STACK_BASE: Cell<Option<usize>> = const { Cell::new(None) };
+    // The size of this thread's stack, if the host has set it
+    static STACK_SIZE: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+// Tells parsing and evaluation on the current thread how big its stack is. Hosts that want
+// deep recursion can run Lisper on a thread spawned with a bigger stack, e.g. with
+// std::thread::Builder::stack_size, and pass the same size here.
+pub fn set_stack_size(size: usize) {
+    STACK_SIZE.with(|stack_size| stack_size.set(Some(size)));
+}
+
+// The size of the current thread's stack, as set by the host, or else the smallest one it's
+// likely to have
+fn stack_size() -> usize {
+    STACK_SIZE.with(|stack_size| stack_size.get().unwrap_or(DEFAULT_STACK_SIZE))
+}
+
+// Threads spawned by std get 2MB of stack, and the main thread gets at least that, apart from
+// on Windows and wasm, where it's 1MB. The real size can't be found out, so larger stacks have
+// to be set with set_stack_size to be used.
+#[cfg(any(windows, target_arch = "wasm32"))]
+const DEFAULT_STACK_SIZE: usize = 1024 * 1024;
+#[cfg(not(any(windows, target_arch = "wasm32")))]
+const DEFAULT_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+// Measures the stack used by nested parse and eval calls, checking it stays within half of the
+// thread's stack. Deeper code is an error rather than overflowing the stack, which would abort
+// the process, and the other half leaves room for the frames of built-in functions and errors.
+// The outermost call records where the stack started, and clears it again when it's dropped.
+struct StackGuard {
+    outermost: bool,
+}
+
+impl StackGuard {
+    fn enter() -> Result<StackGuard, LisperErr> {
+        let marker = 0u8;
+        let here = &marker as *const u8 as usize;
+        STACK_BASE.with(|base| match base.get() {
+            None => {
+                base.set(Some(here));
+                Ok(StackGuard { outermost: true })
+            },
+            // The stack grows down, towards lower addresses
+            Some(start) if start.saturating_sub(here) > stack_size() / 2 => {
+                Err(LisperErr::ResourceLimit("maximum recursion depth exceeded".to_string()))
+            },
+            Some(_) => Ok(StackGuard { outermost: false })
+        })
+    }
+}
+
+impl Drop for StackGuard {
+    fn drop(&mut self) {
+        if self.outermost {
+            STACK_BASE.with(|base| base.set(None));
+        }
+    }
+}
+
+// Parses an array of tokens and creates corresponding LisperExp objects
+pub fn parse(tokens: &[Token]) -> Result<(LisperExp, &[Token]), LisperErr> {
+    let _guard = StackGuard::enter().map_err(|e| match tokens.first() {
+        Some(token) => e.with_span(token.span),
+        None => e,
+    })?;
+    let (first, rest) = tokens.split_first()
+        .ok_or_else(|| LisperErr::Syntax("unexpected end of input.".to_string()))?;
+
+    match first.text.as_str() {
+        "(" => {
+            let (items, span, rest) = parse_seq(first, rest, ")", "list")?;
+            Ok((LisperExp::List(items, Some(span)), rest))
+        },
+        "{" => {
+            // Map literals hold pairs of key and value expressions, e.g. {:a 1 :b (+ 1 1)}
+            let (items, span, rest) = parse_seq(first, rest, "}", "map")?;
+            if !items.len().is_multiple_of(2) {
+                return Err(LisperErr::Syntax("map needs a value for every key.".to_string()).with_span(span));
+            }
+            let pairs = items.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+            Ok((LisperExp::MapLiteral(pairs), rest))
+        },
+        "[" => {
+            let (items, _, rest) = parse_seq(first, rest, "]", "vector")?;
+            Ok((LisperExp::Vector(items), rest))
+        },
+        ")" | "}" | "]" => {
+            Err(LisperErr::Syntax(format!("found unexpected {}.", first.text)).with_span(first.span))
         },
-        ")" => {
-            Err(LisperErr::Reason("Parsing error, found unexpected ).".to_string()))
+        "#;" => {
+            // Datum comment, skip the next expression and parse the one after it
+            let (_, after_comment) = parse(rest)?;
+            parse(after_comment)
+        },
+        "'" | "`" | "," | ",@" => {
+            // Reader shorthand for quoting, e.g. 'x is read as (quote x)
+            let form = match first.text.as_str() {
+                "'" => "quote",
+                "`" => "quasiquote",
+                "," => "unquote",
+                _ => "unquote-splicing"
+            };
+            let (quoted, rest) = parse(rest)?;
+            let span = first.span.to(tokens[tokens.len() - rest.len() - 1].span);
+            Ok((LisperExp::List(vec![LisperExp::Symbol(form.to_string()), quoted], Some(span)), rest))
+        },
+        "#|" => {
+            Err(LisperErr::Syntax("block comment is missing a closing |#.".to_string()).with_span(first.span))
+        },
+        _ if first.text.starts_with('"') => {
+            Ok((parse_string(first)?, rest))
+        },
+        _ if first.text.starts_with("#:") => {
+            // Names made by gensym, which are kept apart from any name in source
+            Err(LisperErr::Syntax(format!("{} is a generated symbol, which can't be read.", first.text)).with_span(first.span))
+        },
+        _ if Ratio::parse(&first.text).is_some_and(|(_, denom)| denom.is_zero()) => {
+            Err(LisperErr::Syntax(format!("{} has a denominator of zero.", first.text)).with_span(first.span))
         },
         _ => {
-            let parsed_token:LisperExp = parse_token(&first);
+            let parsed_token:LisperExp = parse_token(&first.text);
             Ok((parsed_token, rest))
         }
     }
 }
 
+// Parses the expressions of a list, map or vector up to its closing token
+fn parse_seq<'a>(open: &Token, tokens: &'a [Token], close: &str, kind: &str) -> Result<(Vec<LisperExp>, Span, &'a [Token]), LisperErr> {
+    let mut items: Vec<LisperExp> = vec![];
+    let mut more = tokens;
+    loop {
+        let (next, more_next) = more.split_first()
+            .ok_or_else(||
+                LisperErr::Syntax(format!("{} is missing a closing {}.", kind, close)).with_span(open.span)
+            )?;
+        if next.text == close {
+            return Ok((items, open.span.to(next.span), more_next))
+        }
+        if next.text == "#;" {
+            // Datum comment, skip the next expression
+            let (_, after_comment) = parse(more_next)?;
+            more = after_comment;
+            continue;
+        }
+        let (exp, new_more) = parse(more)?;
+        items.push(exp);
+        more = new_more;
+    }
+}
+
+// Parses every top-level expression in an array of tokens, returning each one
+// along with the span of source it was read from
+pub fn parse_program(tokens: &[Token]) -> Result<Vec<(LisperExp, Span)>, LisperErr> {
+    let mut program: Vec<(LisperExp, Span)> = vec![];
+    let mut rest = tokens;
+    loop {
+        // Skip datum comments here, since there may be nothing after them to parse
+        while let Some((first, after_first)) = rest.split_first() {
+            if first.text != "#;" {
+                break;
+            }
+            let (_, after_comment) = parse(after_first)?;
+            rest = after_comment;
+        }
+
+        if rest.is_empty() {
+            return Ok(program);
+        }
+
+        let (exp, more) = parse(rest)?;
+        let span = rest[0].span.to(rest[rest.len() - more.len() - 1].span);
+        program.push((exp, span));
+        rest = more;
+    }
+}
+
+// Parses a string literal token, including its quotes, into a LisperExp::Str,
+// decoding the \n, \t, \", \\ and \u{...} escape sequences
+fn parse_string(token: &Token) -> Result<LisperExp, LisperErr> {
+    let unterminated = || LisperErr::Syntax("string is missing a closing \".".to_string()).with_span(token.span);
+
+    let mut parsed = String::new();
+    let mut chars = token.text.chars().skip(1);
+    loop {
+        match chars.next().ok_or_else(unterminated)? {
+            // The tokenizer ends string tokens at the closing quote
+            '"' => return Ok(LisperExp::Str(parsed)),
+            '\\' => match chars.next().ok_or_else(unterminated)? {
+                'n' => parsed.push('\n'),
+                't' => parsed.push('\t'),
+                '"' => parsed.push('"'),
+                '\\' => parsed.push('\\'),
+                'u' => {
+                    // Unicode escape, e.g. \u{1F600}
+                    let escape: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    let code = escape.strip_prefix('{')
+                                     .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                     .and_then(char::from_u32)
+                                     .ok_or_else(|| 
+                                         LisperErr::Syntax(format!("invalid unicode escape \\u{}}}.", escape)).with_span(token.span)
+                                     )?;
+                    parsed.push(code);
+                },
+                other => {
+                    return Err(LisperErr::Syntax(format!("unknown escape sequence \\{}.", other)).with_span(token.span));
+                }
+            },
+            c => parsed.push(c),
+        }
+    }
+}
+
 // Parses an individual token and creates either a Number of Symbol LisperExp
 fn parse_token(token: &str) -> LisperExp {
     if let Result::Ok(parsed_bool) = token.parse::<bool>() {
         LisperExp::Bool(parsed_bool)
+    } else if let Result::Ok(parsed_int) = token.parse::<i64>() {
+        LisperExp::Int(parsed_int)
+    } else if let Some(parsed_big) = BigInt::parse(token) {
+        // Too big for an i64
+        LisperExp::BigInt(parsed_big)
+    } else if let Some((numer, denom)) = Ratio::parse(token).filter(|(_, denom)| !denom.is_zero()) {
+        // Fractions, e.g. 1/3, in lowest terms
+        Num::fraction(numer, denom).into_exp()
     } else if let Result::Ok(parsed_value) = token.parse::<f64>() {
         LisperExp::Number(parsed_value)
+    } else if let Some(name) = token.strip_prefix(':').filter(|name| !name.is_empty()) {
+        LisperExp::Keyword(name.to_string())
     } else {
         LisperExp::Symbol(token.to_string())
     }
 }
 
-// Evaluates a given Lisp expression, and returns a new one with the result.
-pub fn eval(exp: LisperExp, env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
-    match exp {
-        LisperExp::List(list) => {
-            eval_list(list, env)
-        },
-        LisperExp::Number(num) => {
-            // If it's just a number, then return the number
-            Ok(LisperExp::Number(num))
-        },
-        LisperExp::Symbol(sym) => {
-            let lisper_exp = env.data.get(&sym)
-            .ok_or_else(|| 
-                // We shouldn't be evaluating function symbols here, since they should be
-                // wrapped in lists above. Something is wrong, return an error.
-                LisperErr::Reason("Eval issue, not a real expression".to_string())
-            )?;
+// The outcome of evaluating a special form or function call. Expressions in tail
+// position are handed back to eval to continue with, rather than evaluated
+// recursively, so loops written as recursion run in constant Rust stack.
+enum Step {
+    Done(LisperExp),
+    Tail(LisperExp, LisperEnv),
+    // The tail of a user defined function's body, along with the function's name
+    Call(String, LisperExp, LisperEnv),
+}
+
+// Evaluates a given Lisp expression, and returns a new one with the result.
+pub fn eval(exp: LisperExp, env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let _guard = StackGuard::enter()?;
+    let mut exp = exp;
+    let mut env = env.clone();
+    // The innermost list being evaluated, used to locate errors from atoms in tail position
+    let mut last_span: Option<Span> = None;
+    // The function whose body is being evaluated, added to the call stack of errors.
+    // Tail calls replace it, the same way they don't grow the Rust stack.
+    let mut function: Option<String> = None;
+
+    loop {
+        let step = match exp {
+            LisperExp::List(list, span) => {
+                last_span = span.or(last_span);
+                eval_list(list, &mut env)
+            },
+            LisperExp::Int(_) | LisperExp::BigInt(_) | LisperExp::Ratio(_) | LisperExp::Number(_) => {
+                // If it's just a number, then return the number
+                Ok(Step::Done(exp))
+            },
+            LisperExp::Keyword(name) => {
+                // Keywords, like :name, evaluate to themselves
+                Ok(Step::Done(LisperExp::Keyword(name)))
+            },
+            LisperExp::Symbol(sym) => {
+                env.get(&sym)
+                   .map(Step::Done)
+                   .ok_or_else(|| LisperErr::UnboundSymbol(sym.to_string()))
+            },
+            LisperExp::Bool(b) => {
+                Ok(Step::Done(LisperExp::Bool(b)))
+            },
+            LisperExp::Str(s) => {
+                // Strings evaluate to themselves
+                Ok(Step::Done(LisperExp::Str(s)))
+            },
+            // Built-in functions are values too, e.g. when a macro splices one into its expansion
+            LisperExp::Func(f) => Ok(Step::Done(LisperExp::Func(f))),
+            LisperExp::Lambda(lambda) => {
+                // Closures are values, so they evaluate to themselves
+                Ok(Step::Done(LisperExp::Lambda(lambda)))
+            },
+            LisperExp::Macro(mac) => Ok(Step::Done(LisperExp::Macro(mac))),
+            LisperExp::Map(map) => eval_map(map, &mut env),
+            LisperExp::MapLiteral(pairs) => eval_map_literal(pairs, &mut env),
+            LisperExp::Vector(items) => {
+                // The items of vector literals are evaluated, e.g. [x (+ x 1)]
+                items.into_iter().map(|item| eval(item, &mut env)).collect::<Result<Vec<_>, _>>()
+                     .map(|items| Step::Done(LisperExp::Vector(items)))
+            },
+        };
+
+        // Point any error that doesn't yet have a location at the expression being evaluated
+        let step = step.map_err(|e| match last_span {
+            Some(span) => e.with_span(span),
+            None => e,
+        });
+        match step.map_err(|e| match &function {
+            Some(name) => e.in_function(name),
+            None => e,
+        })? {
+            Step::Done(res) => return Ok(res),
+            Step::Tail(tail_exp, tail_env) => {
+                exp = tail_exp;
+                env = tail_env;
+            },
+            Step::Call(name, tail_exp, tail_env) => {
+                function = Some(name);
+                exp = tail_exp;
+                env = tail_env;
+            }
+        }
+    }
+}
+
+// Evaluates the top-level expressions of a program in order, and returns all of the results.
+// Stops at the first error, pointing it at the expression that failed if nothing more
+// specific is known.
+pub fn eval_program(program: Vec<(LisperExp, Span)>, env: &mut LisperEnv) -> Result<Vec<LisperExp>, LisperErr> {
+    program.into_iter()
+           .map(|(exp, span)| eval(exp, env).map_err(|e| e.with_span(span)))
+           .collect()
+}
+
+// Evaluates the keys and values of a map literal from left to right, and returns the map
+// of the results. If keys evaluate to the same value, the last one is kept.
+fn eval_map_literal(pairs: Vec<(LisperExp, LisperExp)>, env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    let mut evaluated: Vec<(LisperExp, LisperExp)> = Vec::with_capacity(pairs.len());
+    for (key, value) in pairs {
+        let key = eval(key, env)?;
+        evaluated.push((key, eval(value, env)?));
+    }
+    Ok(Step::Done(LisperExp::Map(evaluated.into_iter().collect())))
+}
+
+// Evaluates the keys and values of a map put into code as a value, e.g. by a macro, in the
+// order the map is printed in
+#[allow(clippy::mutable_key_type)]
+fn eval_map(map: HashMap<LisperExp, LisperExp>, env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    let pairs = map_entries(&map).into_iter().map(|(key, value)| (key.clone(), value.clone())).collect();
+    eval_map_literal(pairs, env)
+}
+
+// Turns code into the value quote gives for it, which is the code itself, apart from map
+// literals becoming maps. If keys are the same, the last one is kept.
+fn datum(exp: &LisperExp) -> LisperExp {
+    match exp {
+        LisperExp::List(items, span) => LisperExp::List(items.iter().map(datum).collect(), *span),
+        LisperExp::Vector(items) => LisperExp::Vector(items.iter().map(datum).collect()),
+        LisperExp::MapLiteral(pairs) => LisperExp::Map(pairs.iter().map(|(key, value)| (datum(key), datum(value))).collect()),
+        _ => exp.clone()
+    }
+}
+
+// Evaluates a list of Lisp expressions, and returns a new one with the result.
+fn eval_list(list: Vec<LisperExp>, env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    // Split the symbol from the arguments, the empty list is nil and evaluates to itself
+    let Some((first, args)) = list.split_first() else {
+        return Ok(Step::Done(LisperExp::List(vec![], None)));
+    };
+    match first {
+        LisperExp::Symbol(sym) => {
+            // Catch def, fn and if, and else evalue as a regular env function
+            eval_symbol(sym.clone(), args, env)
+        },
+        _ => {
+            // The function is itself an expression, e.g. ((make-adder 1) 2)
+            eval_call(first, args, env)
+        }
+    }
+}
+
+// Evalute environment and user defined symbols for control flows (if statements),
+// variables (def), and functions (fn). Each special form has its own function, which keeps
+// the stack frame of this one small, as it is on the stack once for every nested call.
+fn eval_symbol(sym: String, args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    match sym.as_str() {
+        "if" => eval_if(args, env),
+        "cond" => eval_cond(args, env),
+        "case" => eval_case(args, env),
+        "when" | "unless" => eval_when(&sym, args, env),
+        "and" | "or" => eval_and_or(&sym, args, env),
+        "not" => eval_not(args, env),
+        "def" => eval_def(args, env),
+        "set!" => eval_set(args, env),
+        "fn" => eval_fn(args, env),
+        "lambda" => eval_lambda(args, env),
+        "defmacro" => eval_defmacro(args, env),
+        "quote" => eval_quote(args),
+        "quasiquote" => eval_quasiquote(args, env),
+        "unquote" | "unquote-splicing" => {
+            Err(LisperErr::Syntax(format!("{} is only valid inside quasiquote.", sym)))
+        },
+        "macroexpand-1" | "macroexpand" => eval_macroexpand(&sym, args, env),
+        "let" | "let*" | "letrec" => eval_let(&sym, args, env),
+        "begin" | "do" => eval_begin(&sym, args, env),
+        "error" | "throw" => eval_throw(&sym, args, env),
+        "try" => eval_try(args, env),
+        _ => {
+            eval_call(&LisperExp::Symbol(sym), args, env)
+        }
+    }
+}
+
+// Evaluates an if statement
+// Format: (if (expression[as LisperExp]) (if true[as LisperExp]) (if false[as LisperExp]))
+fn eval_if(args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.len() != 3 {
+        Err(LisperErr::Syntax("if only takes 3 arguments, if expression, true expression, and false expression.".to_string()))
+    } else {
+        // The chosen branch is in tail position
+        let if_exp:LisperExp = eval(args[0].clone(), env)?;
+        match truthiness(&if_exp) {
+            Some(true) => Ok(Step::Tail(args[1].clone(), env.clone())),
+            Some(false) => Ok(Step::Tail(args[2].clone(), env.clone())),
+            None => Err(LisperErr::type_error("a bool or a number as the if condition", &if_exp))
+        }
+    }
+}
+
+// Evaluates a chain of conditions, the first one that holds has its expressions evaluated
+// Format: (cond ((condition[as LisperExp]) (expression[as LisperExp])...)... (else (expression[as LisperExp])...))
+fn eval_cond(args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    for clause in args.iter() {
+        let clause = match clause {
+            LisperExp::List(clause, _) if !clause.is_empty() => clause,
+            _ => return Err(LisperErr::Syntax("cond clauses must be lists of a condition and expressions.".to_string()))
+        };
+        let holds = match &clause[0] {
+            LisperExp::Symbol(s) if s == "else" => true,
+            condition => {
+                let condition = eval(condition.clone(), env)?;
+                if clause.len() == 1 {
+                    // A clause without expressions returns the condition itself
+                    if is_true("cond", &condition)? {
+                        return Ok(Step::Done(condition));
+                    }
+                    continue;
+                }
+                is_true("cond", &condition)?
+            }
+        };
+        if holds {
+            return eval_sequence(&clause[1..], env.clone());
+        }
+    }
+    // No clause matched
+    Ok(Step::Done(LisperExp::Bool(false)))
+}
+
+// Evaluates a match of a value against lists of literal values
+// Format: (case (expression[as LisperExp]) ((values[as LisperExp]...) (expression[as LisperExp])...)... (else (expression[as LisperExp])...))
+fn eval_case(args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.is_empty() {
+        return Err(LisperErr::Syntax("case takes at least 1 argument, the expression to match.".to_string()));
+    }
+    let key = eval(args[0].clone(), env)?;
+    for clause in args[1..].iter() {
+        let clause = match clause {
+            LisperExp::List(clause, _) if clause.len() > 1 => clause,
+            _ => return Err(LisperErr::Syntax("case clauses must be lists of values and expressions.".to_string()))
+        };
+        let matches = match &clause[0] {
+            LisperExp::Symbol(s) if s == "else" => true,
+            LisperExp::List(values, _) => values.iter().any(|value| is_equal(value, &key)),
+            value => is_equal(value, &key)
+        };
+        if matches {
+            return eval_sequence(&clause[1..], env.clone());
+        }
+    }
+    // No clause matched
+    Ok(Step::Done(LisperExp::Bool(false)))
+}
+
+// Evaluates a condition with a body, when evaluates the body if the condition holds
+// and unless if it doesn't
+// Format: (when (condition[as LisperExp]) (expression[as LisperExp])...)
+fn eval_when(sym: &str, args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.len() < 2 {
+        Err(LisperErr::Syntax(format!("{} takes at least 2 arguments, a condition and an expression.", sym)))
+    } else {
+        let condition = eval(args[0].clone(), env)?;
+        if is_true(sym, &condition)? == (sym == "when") {
+            eval_sequence(&args[1..], env.clone())
+        } else {
+            Ok(Step::Done(LisperExp::Bool(false)))
+        }
+    }
+}
+
+// Evaluates a short-circuiting boolean, and stops at the first value that doesn't
+// hold and or at the first value that does. The last value is in tail position.
+// Format: (and (expression[as LisperExp])...)
+fn eval_and_or(sym: &str, args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    let short_circuit = sym == "or";
+    match args.split_last() {
+        None => Ok(Step::Done(LisperExp::Bool(!short_circuit))),
+        Some((last, init)) => {
+            for arg in init.iter() {
+                let value = eval(arg.clone(), env)?;
+                if is_true(sym, &value)? == short_circuit {
+                    return Ok(Step::Done(value));
+                }
+            }
+            Ok(Step::Tail(last.clone(), env.clone()))
+        }
+    }
+}
+
+// Evaluates a boolean negation
+// Format: (not (expression[as LisperExp]))
+fn eval_not(args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.len() != 1 {
+        Err(LisperErr::Syntax("not only takes 1 argument.".to_string()))
+    } else {
+        let value = eval(args[0].clone(), env)?;
+        Ok(Step::Done(LisperExp::Bool(!is_true("not", &value)?)))
+    }
+}
+
+// Evaluates a variable definition, creating a binding in the current scope
+// Format: (def variable_name[as string] (value[as LisperExp]))
+fn eval_def(args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    // TODO: Figure out if we should block over-writing predefined constants here
+    if args.len() != 2 {
+        Err(LisperErr::Syntax("def only takes 2 arguments, name and an expression.".to_string()))
+    } else {
+        let variable_name:String = symbol_name("def", &args[0])?;
+        let variable_value:LisperExp = eval(args[1].clone(), env)?;
+        
+        env.define(variable_name, variable_value.clone());
+
+        Ok(Step::Done(variable_value))
+    }
+
+}
+
+// Evaluates an assignment, changing the nearest existing binding of a variable
+// Format: (set! variable_name[as string] (value[as LisperExp]))
+fn eval_set(args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.len() != 2 {
+        Err(LisperErr::Syntax("set! only takes 2 arguments, name and an expression.".to_string()))
+    } else {
+        let variable_name:String = symbol_name("set!", &args[0])?;
+        let variable_value:LisperExp = eval(args[1].clone(), env)?;
+
+        env.set(&variable_name, variable_value.clone())?;
+
+        Ok(Step::Done(variable_value))
+    }
+}
+
+// Evaluates a function definition
+// Format: (fn function_name[as string] (arguments[as LisperExp]) (function[as LisperExp])...)
+fn eval_fn(args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.len() < 3 {
+        Err(LisperErr::Syntax("fn takes at least 3 arguments: function name, argument name, and function expression.".to_string()))
+    } else {
+        // Get function name
+        let fn_name:String = args[0].to_string();
+
+        // Collect argument names, followed by the function body
+        let (params, body) = split_params("fn", &args[1..])?;
+        let body: Vec<LisperExp> = body.to_vec();
+
+        // Create Lamba and insert into the current scope
+        let fn_lisper_exp = LisperExp::Lambda(Rc::new(LisperLambda { name: Some(fn_name.to_string()), params, body, env: env.capture() }));
+
+        env.define(fn_name, fn_lisper_exp);
+
+        Ok(Step::Done(LisperExp::Bool(true)))
+    }
+}
+
+// Evaluates an anonymous function, closing over the current scope
+// Format: (lambda (arguments[as LisperExp::List]) (function[as LisperExp])...)
+fn eval_lambda(args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.len() < 2 {
+        Err(LisperErr::Syntax("lambda takes at least 2 arguments: a list of argument names, and function expression.".to_string()))
+    } else {
+        let params = match &args[0] {
+            LisperExp::List(params, _) => parse_params("lambda", params)?,
+            _ => return Err(LisperErr::Syntax("lambda arguments must be a list of names.".to_string()))
+        };
+        let body: Vec<LisperExp> = args[1..].to_vec();
+
+        Ok(Step::Done(LisperExp::Lambda(Rc::new(LisperLambda { name: None, params, body, env: env.capture() }))))
+    }
+}
+
+// Evaluates a macro definition, which takes the same shape as fn. When the macro is
+// called its arguments are passed in unevaluated, and the code it returns is
+// evaluated in their place.
+// Format: (defmacro macro_name[as string] (arguments[as LisperExp]) (expansion[as LisperExp])...)
+fn eval_defmacro(args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.len() < 2 {
+        Err(LisperErr::Syntax("defmacro takes at least 2 arguments: macro name, and expansion expression.".to_string()))
+    } else {
+        let macro_name:String = symbol_name("defmacro", &args[0])?;
+
+        let (params, body) = split_params("defmacro", &args[1..])?;
+        let body: Vec<LisperExp> = body.to_vec();
+
+        env.define(macro_name.to_string(), LisperExp::Macro(Rc::new(LisperLambda { name: Some(macro_name), params, body, env: env.capture() })));
+
+        Ok(Step::Done(LisperExp::Bool(true)))
+    }
+}
+
+// Evaluates quoted code, returning it as data
+// Format: (quote (expression[as LisperExp])) or 'expression
+fn eval_quote(args: &[LisperExp]) -> Result<Step, LisperErr> {
+    if args.len() != 1 {
+        Err(LisperErr::Syntax("quote only takes 1 argument.".to_string()))
+    } else {
+        Ok(Step::Done(datum(&args[0])))
+    }
+}
+
+// Evaluates a code template, quoted apart from the parts marked with unquote
+// Format: (quasiquote (expression[as LisperExp])) or `expression
+fn eval_quasiquote(args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.len() != 1 {
+        Err(LisperErr::Syntax("quasiquote only takes 1 argument.".to_string()))
+    } else {
+        Ok(Step::Done(quasiquote(&args[0], 0, env)?))
+    }
+}
+
+// Expands a quoted macro call, once or until it is no longer a macro call.
+// The argument is evaluated, so the call to expand is usually quoted.
+// Format: (macroexpand (expression[as LisperExp]))
+fn eval_macroexpand(sym: &str, args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.len() != 1 {
+        Err(LisperErr::Syntax(format!("{} only takes 1 argument.", sym)))
+    } else {
+        let mut form = eval(args[0].clone(), env)?;
+        while let Some((mac, mac_args)) = macro_call(&form, env) {
+            form = expand_macro(&mac, &mac_args)?;
+            if sym == "macroexpand-1" {
+                break;
+            }
+        }
+        Ok(Step::Done(form))
+    }
+}
+
+// Evaluates a set of local bindings
+// Format: (let ((name[as string] value[as LisperExp]) ...) (body[as LisperExp])...)
+// let evaluates every value in the enclosing scope, let* sees the bindings before
+// it, and letrec sees all of its bindings, so local functions can call each other
+fn eval_let(sym: &str, args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.len() < 2 {
+        Err(LisperErr::Syntax(format!("{} takes at least 2 arguments: a list of bindings, and an expression.", sym)))
+    } else {
+        let bindings = parse_bindings(sym, &args[0])?;
+        let scope = match sym {
+            "let" => {
+                let mut values: Vec<(String, LisperExp)> = vec![];
+                for (name, value_exp) in bindings {
+                    values.push((name, eval(value_exp, env)?));
+                }
+                let scope = env.extend();
+                for (name, value) in values {
+                    scope.define(name, value);
+                }
+                scope
+            },
+            "let*" => {
+                let mut scope = env.clone();
+                for (name, value_exp) in bindings {
+                    let value = eval(value_exp, &mut scope)?;
+                    scope = scope.extend();
+                    scope.define(name, value);
+                }
+                scope.extend()
+            },
+            _ => {
+                let mut scope = env.extend();
+                for (name, value_exp) in bindings {
+                    let value = eval(value_exp, &mut scope)?;
+                    scope.define(name, value);
+                }
+                scope
+            }
+        };
+
+        eval_sequence(&args[1..], scope)
+    }
+}
+
+// Evaluates a sequence of expressions in order
+// Format: (begin (expression[as LisperExp])...)
+fn eval_begin(sym: &str, args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.is_empty() {
+        Err(LisperErr::Syntax(format!("{} takes at least 1 expression.", sym)))
+    } else {
+        eval_sequence(args, env.clone())
+    }
+}
+
+
+// Evaluates a raised error, which carries any value, usually a message string
+// Format: (error (value[as LisperExp])) or (throw (value[as LisperExp]))
+fn eval_throw(sym: &str, args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    if args.len() != 1 {
+        Err(LisperErr::Syntax(format!("{} only takes 1 argument, the error value.", sym)))
+    } else {
+        Err(LisperErr::User(eval(args[0].clone(), env)?))
+    }
+}
+
+// Evaluates a try form. If the body raises an error, the first catch clause for its kind, or
+// for any kind, is evaluated with the error value bound to a name. The finally clause is
+// always evaluated last, and its result is ignored.
+// Format: (try (expression[as LisperExp])... (catch [:kind] name[as string] (expression[as LisperExp])...)... (finally (expression[as LisperExp])...))
+fn eval_try(args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    let clause = |exp: &LisperExp| match exp {
+        LisperExp::List(items, _) => match items.split_first() {
+            Some((LisperExp::Symbol(head), rest)) if head == "catch" || head == "finally" => Some((head.to_string(), rest.to_vec())),
+            _ => None
+        },
+        _ => None
+    };
+    let body_len = args.iter().take_while(|a| clause(a).is_none()).count();
+    if body_len == 0 {
+        return Err(LisperErr::Syntax("try takes at least 1 expression before its catch and finally clauses.".to_string()));
+    }
+
+    // Each catch is an optional kind, the name to bind and the handler
+    let mut catches: Vec<(Option<String>, String, Vec<LisperExp>)> = vec![];
+    let mut finally: Option<Vec<LisperExp>> = None;
+    for exp in &args[body_len..] {
+        let invalid = || LisperErr::Syntax(format!("invalid try clause {}, expected (catch [:kind] name expression...) followed by (finally expression...).", exp));
+        match clause(exp) {
+            Some((head, rest)) if head == "catch" && finally.is_none() => match rest.as_slice() {
+                [LisperExp::Keyword(kind), LisperExp::Symbol(name), handler @ ..] if !handler.is_empty() => {
+                    catches.push((Some(kind.to_string()), name.to_string(), handler.to_vec()));
+                },
+                [LisperExp::Symbol(name), handler @ ..] if !handler.is_empty() => {
+                    catches.push((None, name.to_string(), handler.to_vec()));
+                },
+                _ => return Err(invalid())
+            },
+            Some((head, rest)) if head == "finally" && finally.is_none() && !rest.is_empty() => finally = Some(rest),
+            _ => return Err(invalid())
+        }
+    }
+
+    let result = match eval_sequence(&args[..body_len], env.clone()).and_then(run_step) {
+        Err(err) => {
+            let handler = catches.iter().find(|(kind, _, _)| kind.as_ref().is_none_or(|kind| kind == err.kind_name()));
+            match handler {
+                Some((_, name, handler)) => {
+                    let scope = env.extend();
+                    scope.define(name.to_string(), err.value());
+                    eval_sequence(handler, scope).and_then(run_step)
+                },
+                None => Err(err)
+            }
+        },
+        res => res
+    };
+    if let Some(finally) = finally {
+        eval_sequence(&finally, env.clone()).and_then(run_step)?;
+    }
+
+    result.map(Step::Done)
+}
+
+// Gets the name a form binds, which has to be a symbol
+fn symbol_name(form: &str, exp: &LisperExp) -> Result<String, LisperErr> {
+    match exp {
+        LisperExp::Symbol(name) => Ok(name.to_string()),
+        _ => Err(LisperErr::Syntax(format!("{} expects a name, found {}.", form, exp)))
+    }
+}
+
+// Decides whether a condition holds. Bools are taken as they are, and numbers
+// hold when they are greater than 0. Anything else isn't a condition.
+fn truthiness(exp: &LisperExp) -> Option<bool> {
+    match exp {
+        LisperExp::Bool(b) => Some(*b),
+        LisperExp::Number(n) => Some(*n > 0.0),
+        LisperExp::Int(n) => Some(*n > 0),
+        LisperExp::BigInt(n) => Some(!n.is_negative()),
+        LisperExp::Ratio(n) => Some(!n.numer().is_negative()),
+        _ => None
+    }
+}
+
+// Decides whether a condition of the given form holds, or returns an error if
+// the value isn't a condition
+pub(crate) fn is_true(form: &str, exp: &LisperExp) -> Result<bool, LisperErr> {
+    truthiness(exp).ok_or_else(|| 
+        LisperErr::type_error(&format!("a bool or a number as the {} condition", form), exp)
+    )
+}
+
+// Evaluates a sequence of expressions and returns the result of the last one,
+// which is in tail position
+fn eval_sequence(exps: &[LisperExp], mut env: LisperEnv) -> Result<Step, LisperErr> {
+    let (last, init) = exps.split_last()
+        .ok_or_else(|| LisperErr::Syntax("expected at least 1 expression.".to_string()))?;
+    for exp in init {
+        eval(exp.clone(), &mut env)?;
+    }
+    Ok(Step::Tail(last.clone(), env))
+}
+
+// Evaluates a function call, applying the function to the evaluated arguments
+fn eval_call(operator: &LisperExp, args: &[LisperExp], env: &mut LisperEnv) -> Result<Step, LisperErr> {
+    // Get the function based on the symbol, or evaluate the expression that creates it
+    let func = match operator {
+        LisperExp::Symbol(sym) => env.get(sym).ok_or_else(|| 
+            LisperErr::UnboundSymbol(sym.to_string())
+        )?,
+        _ => eval(operator.clone(), env)?
+    };
+    // Run the function with the args, and return the result
+    match func {
+        LisperExp::Func(lisper_func) => {
+            // It's a env function, so evaluate that
+            // Evaluate each argument
+            let mut evaluated_args: Vec<LisperExp> = vec![];
+            for arg in args.iter() {
+                evaluated_args.push(eval(arg.clone(), env)?)
+            }
+            Ok(Step::Done(lisper_func.call(&evaluated_args, env)?))
+        },
+        LisperExp::Lambda(lambda) => {
+            // It's a lamba function, (fn_name arg_value(s))
+            // Iterate over args and evalute each one
+            let ev_args: Vec<LisperExp> = args.iter()
+                                              .map(|a| eval(a.clone(), env))
+                                              .collect::<Result<Vec<LisperExp>, LisperErr>>()?;
+
+            call_lambda(&lambda, ev_args)
+        },
+        LisperExp::Macro(mac) => {
+            // It's a macro, so expand the unevaluated arguments and evaluate the result in place
+            Ok(Step::Tail(expand_macro(&mac, args)?, env.clone()))
+        },
+        LisperExp::Keyword(name) => {
+            // Keywords look themselves up in a map, e.g. (:name person)
+            let ev_args: Vec<LisperExp> = args.iter()
+                                              .map(|a| eval(a.clone(), env))
+                                              .collect::<Result<Vec<LisperExp>, LisperErr>>()?;
+            Ok(Step::Done(call_keyword(&name, &ev_args)?))
+        },
+        other => Err(LisperErr::type_error("a function to call", &other))
+    }
+}
+
+// Gets the value of a keyword in a map, or the default, or nil, if it isn't there.
+// Format: (:keyword map default)
+fn call_keyword(name: &str, args: &[LisperExp]) -> Result<LisperExp, LisperErr> {
+    let keyword = LisperExp::Keyword(name.to_string());
+    if args.is_empty() || args.len() > 2 {
+        return Err(LisperErr::arity(&keyword.to_string(), 1, Some(2), args.len()));
+    }
+    match &args[0] {
+        LisperExp::Map(map) => Ok(map.get(&keyword).or(args.get(1)).cloned().unwrap_or(LisperExp::List(vec![], None))),
+        other => Err(LisperErr::type_error(&format!("a map for {}", keyword), other))
+    }
+}
+
+// Calls a function value with already evaluated arguments, for native functions that call
+// back into Lisper, e.g. a function they were passed as an argument
+pub fn apply(func: &LisperExp, args: Vec<LisperExp>, env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match func {
+        LisperExp::Func(native) => native.call(&args, env),
+        LisperExp::Lambda(lambda) => run_step(call_lambda(lambda, args)?),
+        LisperExp::Keyword(name) => call_keyword(name, &args),
+        other => Err(LisperErr::type_error("a function to call", other))
+    }
+}
+
+// Fills in a quasiquote template. Unquoted expressions are evaluated, and unquote-splicing
+// inserts the items of a list. Templates can be nested, and only the unquotes belonging
+// to the outermost quasiquote (depth 0) are evaluated.
+fn quasiquote(template: &LisperExp, depth: usize, env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = match template {
+        LisperExp::List(items, _) => items,
+        LisperExp::MapLiteral(pairs) => {
+            // The keys and values of maps can be unquoted too, e.g. `{:total ,n}, and are
+            // filled in from left to right
+            let mut filled: Vec<(LisperExp, LisperExp)> = Vec::with_capacity(pairs.len());
+            for (key, value) in pairs {
+                let key = quasiquote(key, depth, env)?;
+                filled.push((key, quasiquote(value, depth, env)?));
+            }
+            return Ok(LisperExp::Map(filled.into_iter().collect()));
+        },
+        LisperExp::Vector(items) => return Ok(LisperExp::Vector(quasiquote_items(items, depth, env)?)),
+        _ => return Ok(template.clone())
+    };
+    if let [LisperExp::Symbol(form), arg] = items.as_slice() {
+        match form.as_str() {
+            "unquote" if depth == 0 => return eval(arg.clone(), env),
+            "unquote-splicing" if depth == 0 => {
+                return Err(LisperErr::Syntax("unquote-splicing is only valid inside a list.".to_string()));
+            },
+            "unquote" | "unquote-splicing" => {
+                return Ok(LisperExp::List(vec![items[0].clone(), quasiquote(arg, depth - 1, env)?], None));
+            },
+            "quasiquote" => {
+                return Ok(LisperExp::List(vec![items[0].clone(), quasiquote(arg, depth + 1, env)?], None));
+            },
+            _ => {}
+        }
+    }
+
+    Ok(LisperExp::List(quasiquote_items(items, depth, env)?, None))
+}
+
+// Fills in the items of a list or vector template, splicing in any unquote-splicing items
+fn quasiquote_items(items: &[LisperExp], depth: usize, env: &mut LisperEnv) -> Result<Vec<LisperExp>, LisperErr> {
+    let mut filled: Vec<LisperExp> = vec![];
+    for item in items {
+        match item {
+            LisperExp::List(splice, _) if depth == 0 && splice.len() == 2 && splice[0] == LisperExp::Symbol("unquote-splicing".to_string()) => {
+                match eval(splice[1].clone(), env)? {
+                    LisperExp::List(spliced, _) | LisperExp::Vector(spliced) => filled.extend(spliced),
+                    other => return Err(LisperErr::Syntax(format!("unquote-splicing expects a list, found {}.", other)))
+                }
+            },
+            _ => filled.push(quasiquote(item, depth, env)?)
+        }
+    }
+    Ok(filled)
+}
+
+// Finds the macro an expression calls, along with its unevaluated arguments
+fn macro_call(form: &LisperExp, env: &LisperEnv) -> Option<(Rc<LisperLambda>, Vec<LisperExp>)> {
+    match form {
+        LisperExp::List(list, _) => match list.split_first() {
+            Some((LisperExp::Symbol(sym), args)) => match env.get(sym) {
+                Some(LisperExp::Macro(mac)) => Some((mac, args.to_vec())),
+                _ => None
+            },
+            _ => None
+        },
+        _ => None
+    }
+}
+
+// Runs a macro on unevaluated arguments, and returns the code it expands to
+fn expand_macro(mac: &LisperLambda, args: &[LisperExp]) -> Result<LisperExp, LisperErr> {
+    run_step(call_lambda(mac, args.to_vec())?)
+}
+
+// Finishes evaluating a step, for forms that need the result rather than handing it back to eval
+fn run_step(step: Step) -> Result<LisperExp, LisperErr> {
+    match step {
+        Step::Done(res) => Ok(res),
+        Step::Tail(exp, mut env) => eval(exp, &mut env),
+        Step::Call(name, exp, mut env) => eval(exp, &mut env).map_err(|e| e.in_function(&name))
+    }
+}
+
+// Reads the bindings of a let form, a list of (name value) pairs
+fn parse_bindings(form: &str, bindings: &LisperExp) -> Result<Vec<(String, LisperExp)>, LisperErr> {
+    let invalid = || LisperErr::Syntax(format!("{} bindings must be a list of (name value) pairs.", form));
+    match bindings {
+        LisperExp::List(bindings, _) => bindings.iter()
+                                                .map(|binding| match binding {
+                                                    LisperExp::List(pair, _) => match pair.as_slice() {
+                                                        [LisperExp::Symbol(name), value] => Ok((name.to_string(), value.clone())),
+                                                        _ => Err(invalid())
+                                                    },
+                                                    _ => Err(invalid())
+                                                })
+                                                .collect(),
+        _ => Err(invalid())
+    }
+}
+
+// Splits the expressions after a fn or defmacro name into its parameters and its body,
+// which always includes the last expression. Parameters are the names before the body, or
+// a list, which is needed for &optional and &key. Their (name default) pairs could
+// otherwise be mistaken for body code, e.g. (fn area (&key (width 1)) (* width 2)).
+fn split_params<'a>(form: &str, exps: &'a [LisperExp]) -> Result<(LisperParams, &'a [LisperExp]), LisperErr> {
+    let is_marker = |exp: &LisperExp| matches!(exp, LisperExp::Symbol(name) if name.starts_with('&'));
+    if let [LisperExp::List(params, _), _, ..] = exps {
+        if params.iter().any(is_marker) {
+            return Ok((parse_params(form, params)?, &exps[1..]));
+        }
+    }
+
+    let count = exps[.. exps.len() - 1].iter().take_while(|exp| matches!(exp, LisperExp::Symbol(_))).count();
+    let with_defaults = exps[.. count].iter().any(|exp| matches!(exp, LisperExp::Symbol(name) if name == "&optional" || name == "&key"));
+    if with_defaults {
+        return Err(LisperErr::Syntax(format!("{} parameters with &optional or &key must be in a list, e.g. ({} name (x &optional (y 1)) body).", form, form)));
+    }
+    Ok((parse_params(form, &exps[.. count])?, &exps[count ..]))
+}
+
+// Reads a parameter list, e.g. (a b &optional (c 1) &rest more &key (width 10)).
+// Parameters without a default value default to false.
+fn parse_params(form: &str, exps: &[LisperExp]) -> Result<LisperParams, LisperErr> {
+    let invalid = |exp: &LisperExp| LisperErr::Syntax(format!("invalid {} parameter {}.", form, exp));
+    let mut params = LisperParams::default();
+    // Which section of the list we are in, they have to come in this order
+    let mut section = "";
+    let order = ["", "&optional", "&rest", "&key"];
+
+    for exp in exps {
+        let (name, default) = match exp {
+            LisperExp::Symbol(marker) if order.contains(&marker.as_str()) => {
+                let position = |s: &str| order.iter().position(|o| *o == s);
+                if position(marker) <= position(section) {
+                    return Err(LisperErr::Syntax(format!("{} parameters must be in the order &optional, &rest, &key.", form)));
+                }
+                if section == "&rest" && params.rest.is_none() {
+                    return Err(LisperErr::Syntax(format!("{} expects a name after &rest.", form)));
+                }
+                section = order[position(marker).unwrap_or(0)];
+                continue;
+            },
+            LisperExp::Symbol(name) if !name.starts_with('&') => (name.to_string(), LisperExp::Bool(false)),
+            LisperExp::List(pair, _) if section == "&optional" || section == "&key" => match pair.as_slice() {
+                [LisperExp::Symbol(name), default] => (name.to_string(), default.clone()),
+                _ => return Err(invalid(exp))
+            },
+            _ => return Err(invalid(exp))
+        };
+        match section {
+            "" => params.required.push(name),
+            "&optional" => params.optional.push((name, default)),
+            "&rest" if params.rest.is_none() => params.rest = Some(name),
+            "&key" => params.keys.push((name, default)),
+            _ => return Err(invalid(exp))
+        }
+    }
+    if section == "&rest" && params.rest.is_none() {
+        return Err(LisperErr::Syntax(format!("{} expects a name after &rest.", form)));
+    }
+
+    Ok(params)
+}
+
+// Binds the arguments of a call to the function's parameters, checking there are
+// as many as it takes. Missing optional and key parameters get their default value,
+// evaluated in the function's scope so it can refer to the parameters before it.
+fn bind_params(name: &str, params: &LisperParams, args: Vec<LisperExp>, env: &LisperEnv) -> Result<(), LisperErr> {
+    let min = params.required.len();
+    let max = min + params.optional.len();
+    let variadic = params.rest.is_some() || !params.keys.is_empty();
+    if args.len() < min || (args.len() > max && !variadic) {
+        return Err(LisperErr::arity(name, min, Some(max).filter(|_| !variadic), args.len()));
+    }
+
+    let mut args = args.into_iter();
+    for name in &params.required {
+        env.define(name.to_string(), args.next().unwrap_or(LisperExp::Bool(false)));
+    }
+    for (name, default) in &params.optional {
+        let value = match args.next() {
+            Some(value) => value,
+            None => eval(default.clone(), &mut env.clone())?
+        };
+        env.define(name.to_string(), value);
+    }
+
+    let remaining: Vec<LisperExp> = args.collect();
+    if !params.keys.is_empty() {
+        // Named arguments come in pairs of :name value
+        let mut named: HashMap<String, LisperExp> = HashMap::new();
+        for pair in remaining.chunks(2) {
+            let key = match &pair[0] {
+                LisperExp::Keyword(key) => key,
+                other => return Err(LisperErr::type_error("a keyword argument like :name", other))
+            };
+            if !params.keys.iter().any(|(name, _)| name == key) {
+                return Err(LisperErr::Syntax(format!("unknown keyword argument :{}.", key)));
+            }
+            let value = pair.get(1).ok_or_else(||
+                LisperErr::Syntax(format!("keyword argument :{} is missing a value.", key))
+            )?;
+            named.insert(key.to_string(), value.clone());
+        }
+        for (name, default) in &params.keys {
+            let value = match named.remove(name) {
+                Some(value) => value,
+                None => eval(default.clone(), &mut env.clone())?
+            };
+            env.define(name.to_string(), value);
+        }
+    }
+    if let Some(rest) = &params.rest {
+        env.define(rest.to_string(), LisperExp::List(remaining, None));
+    }
+
+    Ok(())
+}
+
+// Calls a user defined function with already evaluated arguments. The last expression
+// of the function body is in tail position, so it is returned for eval to continue with.
+fn call_lambda(lambda: &LisperLambda, ev_args: Vec<LisperExp>) -> Result<Step, LisperErr> {
+    // The arguments live in a new scope on top of the one the function was created in
+    let sub_env = lambda.env.extend();
+
+    // Set the args as a sub_env variables
+    let name = lambda.name.as_deref().unwrap_or("lambda");
+    bind_params(name, &lambda.params, ev_args, &sub_env)?;
+
+    // Evalute lambda function call in new env and return the result, with the last
+    // expression handed back to eval as a call to this function
+    match eval_sequence(&lambda.body, sub_env).map_err(|e| e.in_function(name))? {
+        Step::Tail(exp, env) => Ok(Step::Call(name.to_string(), exp, env)),
+        step => Ok(step)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
+mod tests {
+    // Evaluates every expression in the source, and returns the last result as a string
+    fn eval_source(source: &str, env: &mut crate::env::LisperEnv) -> Result<String, super::LisperErr> {
+        let tokens = super::tokenize(source.to_string());
+        let results = super::eval_program(super::parse_program(&tokens)?, env)?;
+        Ok(results.last().map(|res| res.to_string()).unwrap_or_default())
+    }
+
+    #[test]
+    fn tokenize_expr() {
+        use super::*;
+
+        assert_eq!(tokenize("(+ 1 1)".to_string()), ["(", "+", "1", "1", ")"]);
+    }
+
+    #[test]
+    fn tokenize_expr_spans() {
+        use super::*;
+
+        // Parentheses split tokens even without whitespace, and spans track lines
+        let tokens = tokenize("(+ 1)(*\n  22 3)".to_string());
+        assert_eq!(tokens, ["(", "+", "1", ")", "(", "*", "22", "3", ")"]);
+        assert_eq!(tokens[1].span, Span { start: 1, end: 2, line: 1, col: 2 });
+        assert_eq!(tokens[6].span, Span { start: 10, end: 12, line: 2, col: 3 });
+    }
+
+    #[test]
+    fn tokenize_string_expr() {
+        use super::*;
+
+        // Whitespace, parentheses and escaped quotes stay inside string literals
+        let tokens = tokenize(r#"(def s "a (b) \"c\"")"#.to_string());
+        assert_eq!(tokens, ["(", "def", "s", r#""a (b) \"c\"""#, ")"]);
+    }
+
+    #[test]
+    fn parse_string_expr() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let tokens = tokenize(r#""tab\tline\nquote\"slash\\smile\u{1F600}""#.to_string());
+        let (parsed, _) = parse(&tokens)?;
+        match &parsed {
+            LisperExp::Str(s) => assert_eq!(s, "tab\tline\nquote\"slash\\smile\u{1F600}"),
+            _ => assert!(false)
+        }
+
+        // Display prints the string back as a literal
+        assert_eq!(parsed.to_string(), "\"tab\\tline\\nquote\\\"slash\\\\smile\u{1F600}\"");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_string_errors() {
+        use super::*;
+
+        for source in [r#""no closing quote"#, r#""bad \q escape""#, r#""bad \u{110000} code""#].iter() {
+            let tokens = tokenize(source.to_string());
+            match parse(&tokens) {
+                Err(e) => assert_eq!(e.span().map(|s| s.start), Some(0)),
+                Ok(_) => assert!(false)
+            }
+        }
+    }
+
+    #[test]
+    fn tokenize_comments() {
+        use super::*;
+
+        // Line and nested block comments are dropped, but not inside strings
+        let source = "; header\n(+ 1 #| outer #| inner |# still outer |# 2) ; trailing\n\"; #| kept |#\"";
+        assert_eq!(tokenize(source.to_string()), ["(", "+", "1", "2", ")", "\"; #| kept |#\""]);
+
+        // Comments end the token before them
+        assert_eq!(tokenize("a;b\nc#|d|#e".to_string()), ["a", "c", "e"]);
+    }
+
+    #[test]
+    fn parse_datum_comments() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        // A datum comment skips a whole expression, wherever it appears
+        let tokens = tokenize("#;(ignored (nested)) (+ 1 #;(* 2 2) #; #; 3 4 5)".to_string());
+        let (parsed, rest) = parse(&tokens)?;
+        assert_eq!(parsed.to_string(), "(+ 1 5)");
+        assert!(rest.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_unclosed_block_comment() {
+        use super::*;
+
+        let tokens = tokenize("(+ 1 2) #| never closed (+ 3 4)".to_string());
+        match parse(&tokens[5..]) {
+            Err(e) => assert_eq!(e.span(), Some(Span { start: 8, end: 10, line: 1, col: 9 })),
+            Ok(_) => assert!(false)
+        }
+    }
+
+    #[test]
+    fn parse_expr_missing_paren_span() {
+        use super::*;
+
+        // The error should point at the parenthesis that was never closed
+        let tokens = tokenize("(+ 1 (* 2 2)".to_string());
+        match parse(&tokens) {
+            Err(e) => assert_eq!(e.span(), Some(Span { start: 0, end: 1, line: 1, col: 1 })),
+            Ok(_) => assert!(false)
+        }
+    }
+
+    #[test]
+    fn eval_error_span() -> Result<(), Box<dyn std::error::Error>> {
+        use super::*;
+        use crate::env::create_default_env;
+
+        // The error should point at the innermost expression that failed
+        let source = "(+ 1\n   (foo 2))";
+        let tokens = tokenize(source.to_string());
+        let (exp, _) = parse(&tokens)?;
+        let mut env:LisperEnv = create_default_env();
+
+        match eval(exp, &mut env) {
+            Err(e) => {
+                assert_eq!(e.span(), Some(Span { start: 8, end: 15, line: 2, col: 4 }));
+                assert_eq!(
+                    e.render(source),
+                    "Error = Unbound variable `foo`. (line 2, column 4)\n2 |    (foo 2))\n  |    ^^^^^^^"
+                );
+            },
+            Ok(_) => assert!(false)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_expr() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        // Create a set of valid tokens that we can parse
+        let mock_tokens = tokenize("(+ 1 1)".to_string());
+        
+        // Parse mock tockens, expect back a LisperExp::List
+        let (parsed_tokens, _) = parse(&mock_tokens[..])?;
+        match parsed_tokens {
+            LisperExp::List(list, _) => assert_eq!(list.len(), 3),
+            _ => assert!(false)
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_number_expr() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        // Create a set of valid tokens that we can parse
+        let mock_token = "99";
+        
+        // Parse mock tockens, expect back a LisperExp::List
+        match parse_token(mock_token) {
+            LisperExp::Int(num) => assert_eq!(num, 99),
+            _ => assert!(false)
+        }
+        // Numbers with a decimal point or exponent are floats, and integers too big for
+        // an i64 are big integers
+        assert!(parse_token("99.0") == LisperExp::Number(99.0));
+        assert!(parse_token("-1e3") == LisperExp::Number(-1000.0));
+        assert_eq!(parse_token("-99999999999999999999").to_string(), "-99999999999999999999");
+        assert!(matches!(parse_token("-99999999999999999999"), LisperExp::BigInt(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_symbol_expr() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        // Create a set of valid tokens that we can parse
+        let mock_token = "+";
+        
+        // Parse mock tockens, expect back a LisperExp::List
+        match parse_token(mock_token) {
+            LisperExp::Symbol(sym) => assert_eq!(sym.to_string(), "+".to_string()),
+            _ => assert!(false)
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_bool_expr() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        // Create a set of valid tokens that we can parse
+        let mock_token = "true";
+        
+        // Parse mock tockens, expect back a LisperExp::List
+        match parse_token(mock_token) {
+            LisperExp::Bool(b) => assert!(b),
+            _ => assert!(false)
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_expr_complex() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        
+        // Create a set of valid tokens that we can parse
+        let mock_tokens = tokenize("(+ 1) (* 2 2)".to_string());
+        
+        // Parse mock tockens, expect back a LisperExp::List
+        let (parsed_tokens, _) = parse(&mock_tokens[..])?;
+        match parsed_tokens {
+            LisperExp::List(list, _) => assert_eq!(list.len(), 2),
+            _ => assert!(false)
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_program_exprs() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        // Every top-level form is returned, including ones spanning lines and trailing comments
+        let tokens = tokenize("(def a 1) a\n(+ a\n 1) #;(ignored)".to_string());
+        let program = parse_program(&tokens)?;
+        assert_eq!(program.len(), 3);
+        assert_eq!(program[1].0.to_string(), "a");
+        assert_eq!(program[2].1, Span { start: 12, end: 20, line: 2, col: 1 });
+        Ok(())
+    }
+
+    #[test]
+    fn eval_program_exprs() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        use crate::env::create_default_env;
+
+        let mut env:LisperEnv = create_default_env();
+
+        let tokens = tokenize("(def a 1) (+ a 1) (* a 3)".to_string());
+        let results: Vec<String> = eval_program(parse_program(&tokens)?, &mut env)?.iter()
+                                                                                   .map(|r| r.to_string())
+                                                                                   .collect();
+        assert_eq!(results, ["1", "2", "3"]);
+
+        // Errors in bare atoms point at the whole top-level form
+        let tokens = tokenize("(+ a 1)\nmissing".to_string());
+        match eval_program(parse_program(&tokens)?, &mut env) {
+            Err(e) => assert_eq!(e.span(), Some(Span { start: 8, end: 15, line: 2, col: 1 })),
+            Ok(_) => assert!(false)
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn eval_def() -> Result<(), Box<dyn std::error::Error>> {
+        use super::*;
+        use crate::env::create_default_env;
+
+        // Test if eval handles def
+        // Format: (def variable_name[as LisperExp::Symbol] (value[as LisperExp]))
+
+        let def_exp:LisperExp = LisperExp::List(vec![
+            LisperExp::Symbol("def".to_string()),
+            LisperExp::Symbol("a".to_string()),
+            LisperExp::Number(1.0)
+        ], None);
+
+        let add_exp:LisperExp = LisperExp::List(vec![
+            LisperExp::Symbol("+".to_string()),
+            LisperExp::Symbol("a".to_string()),
+            LisperExp::Number(1.0)
+        ], None);
+        
+        let mut env:LisperEnv = create_default_env();
+
+        if let LisperExp::Number(_) = eval(def_exp, &mut env)? {
+            if let LisperExp::Number(res) = eval(add_exp, &mut env)? {
+                assert_eq!(2.0, res);
+            } else {
+                assert!(false);
+            }
+        } else {
+            assert!(false);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_if() -> Result<(), Box<dyn std::error::Error>> {
+        use super::*;
+        use crate::env::create_default_env;
+
+        // Test if eval handles if
+        // Format: (if (if expression[as LisperExp]) (true expression[as LisperExp]) (false expression[as LisperExp]))
+
+        let if_exp:LisperExp = LisperExp::List(vec![
+            LisperExp::Symbol("<".to_string()),
+            LisperExp::Number(1.0),
+            LisperExp::Number(0.0),
+        ], None);
+        let if_stmnt:LisperExp = LisperExp::List(vec![
+            LisperExp::Symbol("if".to_string()),
+            if_exp,
+            LisperExp::Number(1.0),
+            LisperExp::Number(2.0),
+        ], None);
+        
+        let mut env:LisperEnv = create_default_env();
+
+        if let LisperExp::Number(res) = eval(if_stmnt, &mut env)? {
+            assert_eq!(2.0, res);
+        } else {
+            assert!(false);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_fn() -> Result<(), Box<dyn std::error::Error>> {
+        use super::*;
+        use crate::env::create_default_env;
+
+        // Test if eval handles fn
+        // Format: (fn function_name[as string] (arguments[as LisperExp]) (function[as LisperExp]))
+
+        let def_fn_one_arg_exp:LisperExp = LisperExp::List(vec![
+            LisperExp::Symbol("fn".to_string()),
+            LisperExp::Symbol("add-fn".to_string()),
+            LisperExp::Symbol("a".to_string()),
+            LisperExp::List(vec![
+                LisperExp::Symbol("+".to_string()),
+                LisperExp::Symbol("a".to_string()),
+                LisperExp::Number(1.0)
+            ], None)
+        ], None);
+
+        let fn_call_one_arg_fn_exp:LisperExp = LisperExp::List(vec![
+            LisperExp::Symbol("add-fn".to_string()),
+            LisperExp::Number(1.0)
+        ], None);
+        
+        let mut env:LisperEnv = create_default_env();
+
+        if let LisperExp::Bool(_) = eval(def_fn_one_arg_exp, &mut env)? {
+            if let LisperExp::Number(res) = eval(fn_call_one_arg_fn_exp, &mut env)? {
+                assert_eq!(2.0, res);
+            } else {
+                assert!(false);
+            }
+        } else {
+            assert!(false);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_fn_multiple_arg() -> Result<(), Box<dyn std::error::Error>> {
+        use super::*;
+        use crate::env::create_default_env;
+
+        // Test if eval handles fn
+        // Format: (fn function_name[as string] (arguments[as LisperExp]) (function[as LisperExp]))
+
+        let def_fn_multiple_arg_exp:LisperExp = LisperExp::List(vec![
+            LisperExp::Symbol("fn".to_string()),
+            LisperExp::Symbol("multipe-args-fn".to_string()),
+            LisperExp::Symbol("a".to_string()),
+            LisperExp::Symbol("b".to_string()),
+            LisperExp::Symbol("c".to_string()),
+            LisperExp::Symbol("d".to_string()),
+            LisperExp::Symbol("e".to_string()),
+            LisperExp::List(vec![
+                LisperExp::Symbol("*".to_string()),
+                LisperExp::Symbol("a".to_string()),
+                LisperExp::Symbol("b".to_string()),
+                LisperExp::Symbol("c".to_string()),
+                LisperExp::Symbol("d".to_string()),
+                LisperExp::Symbol("e".to_string())
+            ], None)
+        ], None);
+
+        let fn_call_multiple_arg_fn_exp:LisperExp = LisperExp::List(vec![
+            LisperExp::Symbol("multipe-args-fn".to_string()),
+            LisperExp::Number(1.0),
+            LisperExp::Number(2.0),
+            LisperExp::Number(3.0),
+            LisperExp::Number(4.0),
+            LisperExp::Number(5.0)
+        ], None);
+        
+        let mut env:LisperEnv = create_default_env();
+
+        if let LisperExp::Bool(_) = eval(def_fn_multiple_arg_exp, &mut env)? {
+            if let LisperExp::Number(res) = eval(fn_call_multiple_arg_fn_exp, &mut env)? {
+                assert_eq!(120.0, res);
+            } else {
+                assert!(false);
+            }
+        } else {
+            assert!(false);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_lambda_closure() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // Closures capture the scope they were created in
+        eval_source("(fn make-adder n (lambda (x) (+ x n)))", &mut env)?;
+        eval_source("(def add-one (make-adder 1)) (def add-ten (make-adder 10))", &mut env)?;
+        assert_eq!(eval_source("(add-one 5)", &mut env)?, "6");
+        assert_eq!(eval_source("(add-ten 5)", &mut env)?, "15");
+
+        // The captured value wins over a binding with the same name where it is called
+        assert_eq!(eval_source("(def n 100) (add-one 5)", &mut env)?, "6");
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_lambda_higher_order() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // Closures can be passed as arguments and called without arguments
+        eval_source("(fn twice f x (f (f x)))", &mut env)?;
+        assert_eq!(eval_source("(twice (lambda (x) (* x 3)) 2)", &mut env)?, "18");
+        assert_eq!(eval_source("(def answer (lambda () 42)) (answer)", &mut env)?, "42");
+
+        // Closures bound with def can call themselves
+        eval_source("(def fact (lambda (n) (if (< n 2) 1 (* n (fact (- n 1))))))", &mut env)?;
+        assert_eq!(eval_source("(fact 5)", &mut env)?, "120");
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_lambda_invalid() {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        assert!(eval_source("(lambda x (+ x 1))", &mut env).is_err());
+        assert!(eval_source("(lambda (1) 1)", &mut env).is_err());
+        assert!(eval_source("(lambda (x))", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_def_scope() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // def inside a function binds in the call's scope, not the caller's
+        eval_source("(def x 1)", &mut env)?;
+        eval_source("(fn shadow y (+ (def x y) x))", &mut env)?;
+        assert_eq!(eval_source("(shadow 5)", &mut env)?, "10");
+        assert_eq!(eval_source("x", &mut env)?, "1");
+
+        // Functions see globals defined after them, and the latest value of each
+        eval_source("(fn use-later a (+ a later))", &mut env)?;
+        eval_source("(def later 2)", &mut env)?;
+        assert_eq!(eval_source("(use-later 1)", &mut env)?, "3");
+        eval_source("(def later 3)", &mut env)?;
+        assert_eq!(eval_source("(use-later 1)", &mut env)?, "4");
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_tail_calls() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // Recursion in tail position runs in constant stack, however deep it goes
+        eval_source("(fn count-down n (if (< n 1) 0 (count-down (- n 1))))", &mut env)?;
+        assert_eq!(eval_source("(count-down 100000)", &mut env)?, "0");
+
+        // Including through closures and mutually recursive functions
+        eval_source("(def even? (lambda (n) (if (< n 1) true (odd? (- n 1)))))", &mut env)?;
+        eval_source("(def odd? (lambda (n) (if (< n 1) false (even? (- n 1)))))", &mut env)?;
+        assert_eq!(eval_source("(even? 100001)", &mut env)?, "false");
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_let() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+        eval_source("(def x 10)", &mut env)?;
+
+        // let evaluates its values in the enclosing scope
+        assert_eq!(eval_source("(let ((x 1) (y x)) (+ x y))", &mut env)?, "11");
+        // let* sees the bindings before it
+        assert_eq!(eval_source("(let* ((x 1) (y x)) (+ x y))", &mut env)?, "2");
+        // The bindings don't leak out of the form
+        assert_eq!(eval_source("(let ((x 1) (z 2)) z) x", &mut env)?, "10");
+        assert!(eval_source("z", &mut env).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_letrec() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // Local functions under letrec can call each other
+        let source = "(letrec ((ev? (lambda (n) (if (< n 1) true (od? (- n 1)))))
+                               (od? (lambda (n) (if (< n 1) false (ev? (- n 1))))))
+                        (od? 7))";
+        assert_eq!(eval_source(source, &mut env)?, "true");
+        assert!(eval_source("ev?", &mut env).is_err());
+
+        // Bindings must be (name value) pairs
+        assert!(eval_source("(let (x 1) x)", &mut env).is_err());
+        assert!(eval_source("(letrec ((1 x)) x)", &mut env).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_begin() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // Every expression is evaluated in order, in the current scope, and the last is returned
+        assert_eq!(eval_source("(begin (def a 1) (def b (+ a 1)) (* b 10))", &mut env)?, "20");
+        assert_eq!(eval_source("(do (def a 5) a)", &mut env)?, "5");
+        assert_eq!(eval_source("a", &mut env)?, "5");
+        assert!(eval_source("(begin)", &mut env).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_multi_form_bodies() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        eval_source("(fn sum-squares a b (def a2 (* a a)) (def b2 (* b b)) (+ a2 b2))", &mut env)?;
+        assert_eq!(eval_source("(sum-squares 3 4)", &mut env)?, "25");
+        assert!(eval_source("a2", &mut env).is_err());
+
+        // A function body can be a single symbol
+        eval_source("(fn identity x x)", &mut env)?;
+        assert_eq!(eval_source("(identity 7)", &mut env)?, "7");
+
+        assert_eq!(eval_source("((lambda (x) (def y (* x 2)) (+ x y)) 3)", &mut env)?, "9");
+        assert_eq!(eval_source("(let ((x 2)) (def y 3) (* x y))", &mut env)?, "6");
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_operator_expression() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // The operator is evaluated like any other expression before it's applied
+        eval_source("(fn make-adder n (lambda (x) (+ x n)))", &mut env)?;
+        assert_eq!(eval_source("((make-adder 1) 2)", &mut env)?, "3");
+        assert_eq!(eval_source("((if true + *) 2 3)", &mut env)?, "5");
+        assert!(eval_source("(1 2 3)", &mut env).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_cond() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        eval_source("(fn sign n (cond ((< n 0) \"negative\") ((> n 0) \"positive\") (else \"zero\")))", &mut env)?;
+        assert_eq!(eval_source("(sign -5)", &mut env)?, "\"negative\"");
+        assert_eq!(eval_source("(sign 5)", &mut env)?, "\"positive\"");
+        assert_eq!(eval_source("(sign 0)", &mut env)?, "\"zero\"");
+
+        // Clauses without expressions return their condition, and nothing matching returns false
+        assert_eq!(eval_source("(cond (false 1) (3))", &mut env)?, "3");
+        assert_eq!(eval_source("(cond (false 1))", &mut env)?, "false");
+        assert!(eval_source("(cond (\"not a condition\" 1))", &mut env).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_case() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        eval_source("(fn describe n (case n ((1 2 3) \"small\") ((\"ten\" 10) (def big true) \"ten\") (else \"other\")))", &mut env)?;
+        assert_eq!(eval_source("(describe 2)", &mut env)?, "\"small\"");
+        assert_eq!(eval_source("(describe (* 5 2))", &mut env)?, "\"ten\"");
+        assert_eq!(eval_source("(describe \"ten\")", &mut env)?, "\"ten\"");
+        assert_eq!(eval_source("(describe 4)", &mut env)?, "\"other\"");
+        assert_eq!(eval_source("(case true (true 1) (false 0))", &mut env)?, "1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_when_unless() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        assert_eq!(eval_source("(when (> 2 1) (def a 1) (+ a 1))", &mut env)?, "2");
+        assert_eq!(eval_source("(when (< 2 1) (def b 1))", &mut env)?, "false");
+        assert!(eval_source("b", &mut env).is_err());
+        assert_eq!(eval_source("(unless (< 2 1) 3)", &mut env)?, "3");
+        assert_eq!(eval_source("(unless (> 2 1) 3)", &mut env)?, "false");
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_and_or_not() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        assert_eq!(eval_source("(and)", &mut env)?, "true");
+        assert_eq!(eval_source("(or)", &mut env)?, "false");
+        assert_eq!(eval_source("(and true 1 2)", &mut env)?, "2");
+        assert_eq!(eval_source("(or false 0 3)", &mut env)?, "3");
+        assert_eq!(eval_source("(not (< 2 1))", &mut env)?, "true");
+        assert_eq!(eval_source("(not 1)", &mut env)?, "false");
+
+        // Only as many arguments as needed are evaluated
+        assert_eq!(eval_source("(and false (def a 1))", &mut env)?, "false");
+        assert_eq!(eval_source("(or true (def a 1))", &mut env)?, "true");
+        assert!(eval_source("a", &mut env).is_err());
+        assert!(eval_source("(and \"not a condition\" true)", &mut env).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_set() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // set! changes the binding a closure captured, so the change is kept between calls
+        eval_source("(fn make-counter start (lambda () (set! start (+ start 1)) start))", &mut env)?;
+        eval_source("(def counter (make-counter 0))", &mut env)?;
+        eval_source("(counter) (counter)", &mut env)?;
+        assert_eq!(eval_source("(counter)", &mut env)?, "3");
+
+        // and changes globals from inside functions, where def would only bind locally
+        eval_source("(def total 0)", &mut env)?;
+        eval_source("(fn add-to-total n (set! total (+ total n)))", &mut env)?;
+        eval_source("(add-to-total 5) (add-to-total 6)", &mut env)?;
+        assert_eq!(eval_source("total", &mut env)?, "11");
+
+        // but can't create new bindings
+        match eval_source("(set! missing 1)", &mut env) {
+            Err(e) => assert!(e.to_string().starts_with("Unbound variable `missing`.")),
+            Ok(_) => assert!(false)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_unbound_variable() {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        match eval_source("(+ 1 missing)", &mut env) {
+            Err(e) => assert!(e.to_string().starts_with("Unbound variable `missing`.")),
+            Ok(_) => assert!(false)
+        }
+        assert!(eval_source("(def (a) 1)", &mut env).is_err());
+    }
+
+    #[test]
+    fn parse_quote_shorthand() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let tokens = tokenize("'a `(b ,c ,@d)".to_string());
+        assert_eq!(tokens, ["'", "a", "`", "(", "b", ",", "c", ",@", "d", ")"]);
+
+        let program = parse_program(&tokens)?;
+        assert_eq!(program[0].0.to_string(), "(quote a)");
+        assert_eq!(program[1].0.to_string(), "(quasiquote (b (unquote c) (unquote-splicing d)))");
+        assert_eq!(program[1].1, Span { start: 3, end: 14, line: 1, col: 4 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_quote() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        assert_eq!(eval_source("'a", &mut env)?, "a");
+        assert_eq!(eval_source("(quote (+ 1 2))", &mut env)?, "(+ 1 2)");
+        assert_eq!(eval_source("(case 'b ((a b) 1) (else 2))", &mut env)?, "1");
+
+        // Quasiquote only evaluates what is unquoted
+        eval_source("(def xs '(2 3))", &mut env)?;
+        assert_eq!(eval_source("`(1 ,(+ 1 1) xs)", &mut env)?, "(1 2 xs)");
+        assert_eq!(eval_source("`(1 ,@xs 4)", &mut env)?, "(1 2 3 4)");
+        // and leaves unquotes of nested quasiquotes alone
+        assert_eq!(eval_source("`(1 `(2 ,(3 ,(+ 2 2))))", &mut env)?, "(1 (quasiquote (2 (unquote (3 4)))))");
+
+        assert!(eval_source(",xs", &mut env).is_err());
+        assert!(eval_source("`(1 ,@2)", &mut env).is_err());
+        assert!(eval_source("`,@xs", &mut env).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_defmacro() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // Arguments reach the macro unevaluated, so only one branch runs
+        eval_source("(defmacro my-if c then else `(cond (,c ,then) (else ,else)))", &mut env)?;
+        assert_eq!(eval_source("(my-if (< 1 2) 1 (undefined))", &mut env)?, "1");
+
+        eval_source("(defmacro sum-all nums `(+ ,@nums))", &mut env)?;
+        assert_eq!(eval_source("(sum-all (1 2 3))", &mut env)?, "6");
+
+        // Macros can use each other, and macroexpand shows the code they produce
+        eval_source("(defmacro my-unless c body `(my-if ,c false ,body))", &mut env)?;
+        assert_eq!(eval_source("(macroexpand-1 '(my-unless x y))", &mut env)?, "(my-if x false y)");
+        assert_eq!(eval_source("(macroexpand '(my-unless x y))", &mut env)?, "(cond (x false) (else y))");
+        assert_eq!(eval_source("(macroexpand '(+ 1 2))", &mut env)?, "(+ 1 2)");
+
+        // A built-in function spliced into the expansion is called like its name would be
+        eval_source("(defmacro inc-by-one x `(,+ 1 ,x))", &mut env)?;
+        assert_eq!(eval_source("(inc-by-one 2)", &mut env)?, "3");
+
+        assert!(eval_source("(my-if true)", &mut env).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_gensym() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // A generated name can't capture a variable the caller uses
+        eval_source("(defmacro swap! a b (let ((tmp (gensym))) `(let ((,tmp ,a)) (set! ,a ,b) (set! ,b ,tmp))))", &mut env)?;
+        eval_source("(def tmp 1) (def other 2)", &mut env)?;
+        eval_source("(swap! tmp other)", &mut env)?;
+        assert_eq!(eval_source("tmp", &mut env)?, "2");
+        assert_eq!(eval_source("other", &mut env)?, "1");
+
+        assert_eq!(eval_source("(= (gensym) (gensym))", &mut env)?, "false");
+        assert!(eval_source("(gensym \"loop\")", &mut env)?.starts_with("#:loop__"));
+
+        // Names in source can't be the same as a generated one
+        eval_source("(def G__0 1) (def G__1 1)", &mut env)?;
+        assert_eq!(eval_source("(= (gensym) 'G__0)", &mut env)?, "false");
+        let generated = eval_source("(gensym)", &mut env)?;
+        assert!(generated.starts_with("#:G__"));
+        match eval_source(&format!("(def {} 1)", generated), &mut env) {
+            Err(e) => assert!(e.to_string().contains("generated symbol")),
+            Ok(_) => assert!(false)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_optional_and_rest_params() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // Defaults can refer to the parameters before them
+        eval_source("(fn scale (x &optional (by 2) (offset by)) (+ (* x by) offset))", &mut env)?;
+        assert_eq!(eval_source("(scale 3)", &mut env)?, "8");
+        assert_eq!(eval_source("(scale 3 3)", &mut env)?, "12");
+        assert_eq!(eval_source("(scale 3 3 1)", &mut env)?, "10");
+
+        eval_source("(def count-args (lambda (first &rest more) more))", &mut env)?;
+        assert_eq!(eval_source("(count-args 1)", &mut env)?, "()");
+        assert_eq!(eval_source("(count-args 1 2 3)", &mut env)?, "(2 3)");
+
+        // Parameters with defaults have to be in a list, as they could be mistaken for body code
+        match eval_source("(fn h &optional (n 1) (not n) n)", &mut env) {
+            Err(e) => assert!(e.to_string().starts_with("Syntax error, fn parameters with &optional or &key must be in a list")),
+            Ok(_) => assert!(false)
+        }
+        assert!(eval_source("(fn h x &optional y x)", &mut env).is_err());
+        eval_source("(fn h (&optional (n 1)) (not n) n)", &mut env)?;
+        assert_eq!(eval_source("(h)", &mut env)?, "1");
+        assert!(eval_source("(h 5 6)", &mut env).is_err());
+
+        // Macros take the same parameters
+        eval_source("(defmacro my-and &rest conditions `(and ,@conditions))", &mut env)?;
+        assert_eq!(eval_source("(my-and true 1 2)", &mut env)?, "2");
+        assert_eq!(eval_source("(my-and)", &mut env)?, "true");
+
+        match eval_source("(scale)", &mut env) {
+            Err(e) => assert!(e.to_string().starts_with("Arity error, scale expects 1 to 3 arguments, got 0.")),
+            Ok(_) => assert!(false)
+        }
+        match eval_source("(count-args)", &mut env) {
+            Err(e) => assert!(e.to_string().starts_with("Arity error, lambda expects at least 1 arguments, got 0.")),
+            Ok(_) => assert!(false)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_key_params() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        eval_source("(fn area (&key (width 1) (height width)) (* width height))", &mut env)?;
+        assert_eq!(eval_source("(area)", &mut env)?, "1");
+        assert_eq!(eval_source("(area :width 3)", &mut env)?, "9");
+        assert_eq!(eval_source("(area :height 2 :width 3)", &mut env)?, "6");
+        assert_eq!(eval_source(":width", &mut env)?, ":width");
+
+        assert!(eval_source("(area :depth 2)", &mut env).is_err());
+        assert!(eval_source("(area :width)", &mut env).is_err());
+        assert!(eval_source("(area 3)", &mut env).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_params_invalid() {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        assert!(eval_source("(lambda (&rest) 1)", &mut env).is_err());
+        assert!(eval_source("(lambda (&rest a b) 1)", &mut env).is_err());
+        assert!(eval_source("(lambda (&key (a 1) &optional b) 1)", &mut env).is_err());
+        assert!(eval_source("(lambda ((a 1)) 1)", &mut env).is_err());
+        assert!(eval_source("(fn f a 1)", &mut env).is_ok());
+        assert!(eval_source("(f 1 2)", &mut env).is_err());
+    }
+
+    #[test]
+    fn eval_arity_errors() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        eval_source("(fn add-fn a b (+ a b))", &mut env)?;
+        match eval_source("(add-fn 1)", &mut env) {
+            Err(e) => assert!(e.to_string().starts_with("Arity error, add-fn expects 2 arguments, got 1.")),
+            Ok(_) => assert!(false)
+        }
+        assert!(eval_source("(add-fn 1 2 3)", &mut env).is_err());
+        assert!(eval_source("((lambda (x) x))", &mut env).is_err());
+
+        // Errors in arguments are returned rather than panicking
+        match eval_source("(add-fn 1 missing)", &mut env) {
+            Err(e) => assert!(e.to_string().starts_with("Unbound variable `missing`.")),
+            Ok(_) => assert!(false)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn eval_recursion_limit() -> Result<(), Box<dyn std::error::Error>> {
+        use super::*;
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // Recursion that isn't in tail position runs out of stack, which is an error rather than a crash
+        eval_source("(fn depth n (+ 1 (depth n)))", &mut env)?;
+        match eval_source("(depth 1)", &mut env) {
+            Err(e) => assert!(e.to_string().starts_with("Resource limit reached, maximum recursion depth exceeded.")),
+            Ok(_) => assert!(false)
+        }
+        // and the limit is reset for the next expression
+        eval_source("(fn sum-to n (if (< n 1) 0 (+ n (sum-to (- n 1)))))", &mut env)?;
+        assert_eq!(eval_source("(sum-to 50)", &mut env)?, "1275");
+
+        // Threads with a bigger stack allow deeper recursion, once they say how big it is
+        let size = 256 * 1024 * 1024;
+        let deep = std::thread::Builder::new().stack_size(size).spawn(move || {
+            set_stack_size(size);
+            let mut env = create_default_env();
+            ["(fn s n (if (< n 1) 0 (+ 1 (s (- n 1)))))", "(s 10000)", "(fn depth n (+ 1 (depth n)))", "(depth 1)"].iter()
+                .map(|source| eval_source(source, &mut env).map_err(|e| e.to_string()))
+                .collect::<Vec<_>>()
+        })?.join().map_err(|_| "the deep recursion thread panicked")?;
+        assert_eq!(deep[1], Ok("10000".to_string()));
+        match &deep[3] {
+            Err(e) => assert!(e.starts_with("Resource limit reached, maximum recursion depth exceeded.")),
+            Ok(_) => assert!(false)
+        }
+
+        // Deeply nested source is a parsing error
+        let nested = format!("{}1{}", "(".repeat(100000), ")".repeat(100000));
+        assert!(eval_source(&nested, &mut env).is_err());
 
-            // This is actually a def, so return the value 
-            Ok(lisper_exp.clone())
-        },
-        LisperExp::Bool(b) => {
-            Ok(LisperExp::Bool(b))
-        },
-        LisperExp::Func(_) => Err(LisperErr::Reason("Unexpected function".to_string())),
-        LisperExp::Lambda(_) => Err(LisperErr::Reason("Unexpected lambda function".to_string())),
+        Ok(())
     }
-}
 
-// Evaluates a list of Lisp expressions, and returns a new one with the result.
-fn eval_list(list: Vec<LisperExp>, env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
-    // Split the symbol from the arguments
-    let (first, args) = list.split_first()
-        .ok_or_else(|| 
-            LisperErr::Reason("Error reading expression".to_string())
-        )?;
-    match first {
-        LisperExp::List(first) => {
-            match eval(LisperExp::List(first.clone()), env) {
-                Ok(_) => eval(LisperExp::List(args.to_vec()), env),
-                Err(e) => Err(e)
+    // Builds random programs from pieces of Lisper syntax, and checks that tokenizing, parsing,
+    // evaluating and rendering errors never panic, whatever the input
+    #[test]
+    fn fuzz_never_panics() {
+        use super::*;
+        use crate::env::create_default_env;
+
+        let pieces = [
+            "(", "(", "(", ")", ")", ")", "'", "`", ",", ",@", "#;", "#|", "|#", ";", "\n", "\"", "\"a b\"", "\\",
+            "0", "1", "-2.5", "1e308", "true", "false", "x", "y", "f", ":k", "&optional", "&rest", "&key", "(x 1)",
+            "if", "cond", "case", "else", "when", "unless", "and", "or", "not", "def", "set!", "fn", "lambda",
+            "defmacro", "quote", "quasiquote", "unquote", "unquote-splicing", "macroexpand", "macroexpand-1",
+            "let", "let*", "letrec", "begin", "do", "gensym", "+", "-", "*", "/", "%", "<", ">", "=", "<=", ">=",
+            "sin", "cos", "tan", "pi", "\u{1F600}", "\t", "error", "throw", "try", "catch", "finally", ":user",
+            "map", "filter", "reduce", "fold", "apply", "for-each", "sort-by", "any", "every",
+            "list", "cons", "car", "cdr", "nth", "length", "append", "reverse", "last", "take", "drop", "range",
+            "empty?", "nil", "()", "{", "}", "{:a 1}", "hash-map", "get", "assoc", "dissoc", "keys", "vals",
+            "contains?", "merge", "update", "[", "]", "[1 2]", "vector", "vec", "conj", "subvec",
+            "quot", "rem", "9223372036854775807", "-99999999999999999999", "0.5", "1/3", "-4/6", "1/0",
+            "numerator", "denominator", "exact->inexact", "rationalize",
+        ];
+        // A small xorshift generator, so the programs are the same on every run
+        let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = move |max: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % max as u64) as usize
+        };
+        // Random expressions with balanced parentheses, which get further than the parser
+        fn random_exp(next: &mut dyn FnMut(usize) -> usize, pieces: &[&str], depth: usize) -> String {
+            if depth == 0 || next(3) == 0 {
+                return pieces[next(pieces.len())].to_string();
             }
-        },
-        LisperExp::Symbol(sym) => {
-            // Catch def, fn and if, and else evalue as a regular env function
-            eval_symbol(sym.clone(), args, env)
-        },
-        _ => {
-            Err(LisperErr::Reason("Parsing error.".to_string()))
+            let items: Vec<String> = (0..next(5)).map(|_| random_exp(next, pieces, depth - 1)).collect();
+            format!("({})", items.join(" "))
         }
-    }
-}
 
-// Evalute environment and user defined symbols for control flows (if statements),
-// variables (def), and functions (fn)
-fn eval_symbol(sym: String, args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
-    match sym.as_str() {
-        "if" => {
-            // It's an if statement
-            // Format: (if (expression[as LisperExp]) (if true[as LisperExp]) (if false[as LisperExp]))
-            
-            if args.len() != 3 {
-                Err(LisperErr::Reason("Syntax error, if only takes 3 arguments, if expression, true expression, and false expression.".to_string()))
+        for i in 0..5000 {
+            let source: String = if i % 2 == 0 {
+                (0..next(40)).map(|_| {
+                    let piece = pieces[next(pieces.len())];
+                    if next(4) == 0 { piece.to_string() } else { format!("{} ", piece) }
+                }).collect()
             } else {
-                let if_exp:LisperExp = eval(args[0].clone(), env)?;
-                match if_exp {
-                    LisperExp::Bool(res) => {
-                        if res {
-                            Ok(eval(args[1].clone(), env)?)
-                        } else {
-                            Ok(eval(args[2].clone(), env)?)
-                        }
-                    },
-                    LisperExp::Number(res) => {
-                        if res > 0.0 {
-                            Ok(eval(args[1].clone(), env)?)
-                        } else {
-                            Ok(eval(args[2].clone(), env)?)
-                        }
-                    },
-                    _ => Err(LisperErr::Reason("If statement invalid.".to_string()))
+                (0..next(4)).map(|_| random_exp(&mut next, &pieces, 4)).collect::<Vec<String>>().join(" ")
+            };
+
+            let result = std::panic::catch_unwind(|| {
+                let tokens = tokenize(source.clone());
+                let mut env = create_default_env();
+                let outcome = parse_program(&tokens).and_then(|program| eval_program(program, &mut env));
+                if let Err(e) = outcome {
+                    e.render(&source);
                 }
-            }
-        },
-        "def" => {
-            // It's a variable definition
-            // Format: (def variable_name[as string] (value[as LisperExp]))
+            });
+            assert!(result.is_ok(), "panicked on input: {}", source);
+        }
+    }
 
-            // TODO: Figure out if we should block over-writing predefined constants here
-            if args.len() != 2 {
-                Err(LisperErr::Reason("Syntax error, def only takes 2 arguments, name and an expression.".to_string()))
-            } else {
-                let variable_name:String = args[0].to_string();
-                let variable_value:LisperExp = eval(args[1].clone(), env)?;
-                
-                env.data.insert(variable_name, variable_value.clone());
+    #[test]
+    fn eval_error_kinds() -> Result<(), Box<dyn std::error::Error>> {
+        use super::*;
+        use crate::env::create_default_env;
 
-                Ok(variable_value)
-            }
+        let mut env = create_default_env();
 
-        },
-        "fn" => {
-            // It's a function definition
-            // Format: (fn function_name[as string] (arguments[as LisperExp]) (function[as LisperExp]))
-            if args.len() < 3 {
-                Err(LisperErr::Reason("Syntax error, fn takes at least 3 arguments: function name, argument name, and function expression.".to_string()))
-            } else {
-                // Get function name
-                let fn_name:String = args[0].to_string();
+        let kind = |source: &str, env: &mut LisperEnv| match eval_source(source, env) {
+            Err(e) => e.kind().to_string(),
+            Ok(_) => String::new()
+        };
+        eval_source("(fn add-fn a b (+ a b))", &mut env)?;
+        assert_eq!(kind("(add-fn 1)", &mut env), "Arity error, add-fn expects 2 arguments, got 1.");
+        assert_eq!(kind("(if)", &mut env), "Syntax error, if only takes 3 arguments, if expression, true expression, and false expression.");
+        assert_eq!(kind("(+ 1 (", &mut env), "Syntax error, list is missing a closing ).");
+        assert_eq!(kind("missing", &mut env), "Unbound variable `missing`.");
+        assert_eq!(kind("(1 2)", &mut env), "Type error, expected a function to call, found 1.");
 
-                // Collect argument definitions as a list of LisperExp::Symbol
-                let mut fn_def: Vec<LisperExp> = args[1 .. args.len() - 1].iter()
-                                                                    .map(|a| LisperExp::Symbol(a.to_string()))
-                                                                    .collect();
+        match eval_source("(add-fn 1)", &mut env) {
+            Err(e) => {
+                assert!(matches!(e.kind(), LisperErr::Arity { name, got: 1, .. } if name == "add-fn"));
+                // The location wraps the error it points at
+                let source = std::error::Error::source(&e).map(|s| s.to_string());
+                assert_eq!(source.as_deref(), Some("Arity error, add-fn expects 2 arguments, got 1."));
+            },
+            Ok(_) => assert!(false)
+        }
 
-                // Add the function definition to the end
-                fn_def.push(args.last().unwrap().clone());
+        Ok(())
+    }
 
-                // Create Lamba and insert into the current scope
-                let fn_lisper_exp = LisperExp::Lambda(fn_def);
+    #[test]
+    fn eval_error_stack() -> Result<(), Box<dyn std::error::Error>> {
+        use super::*;
+        use crate::env::create_default_env;
 
-                env.data.insert(fn_name, fn_lisper_exp);
+        let mut env = create_default_env();
 
-                Ok(LisperExp::Bool(true))
-            }
-        },
-        _ => {
-            // Get the function based on the symbol
-            let func = env.data.get(&sym.to_string()).ok_or_else(|| 
-                LisperErr::Reason("Error, env function not found.".to_string())
-            )?.clone();
-            // Run the function with the args, and return the result
-            match func {
-                LisperExp::Func(lisper_func) => {
-                    // It's a env function, so evaluate that
-                    // Evaluate each argument
-                    let mut evaluated_args: Vec<LisperExp> = vec![];
-                    for arg in args.iter() {
-                        evaluated_args.push(eval(arg.clone(), env)?)
-                    }
-                    Ok(lisper_func(&LisperExp::List(evaluated_args)))
-                },
-                LisperExp::Lambda(lambda) => {
-                    // It's a lamba function, (fn_name arg_value(s))
-                    if args.len() < 1 {
-                        Err(LisperErr::Reason("Syntax error, a fn call takes at least 1 argument.".to_string()))
-                    } else {
-                        // Iterate over args and evalute each one
-                        let ev_args: Vec<LisperExp> = args[0..args.len()].iter()
-                                                            .map(|a| eval(a.clone(), env)
-                                                            .unwrap())
-                                                            .collect();
-                        
-                        // Create new env to be the inherited sub-scope
-                        let mut sub_env = env.clone();
-                        
-                        // Set the args as a sub_env variables
-                        // Iterate over lambda from 0 .. len - 1 to get all args
-                        for i  in 0 .. lambda.len() - 1 {
-                            let arg_def = lambda[i].to_string();
-                            let arg_ev = ev_args.get(i).unwrap().clone();
-                            sub_env.data.insert(arg_def, arg_ev);
-                        }
-
-                        // Get the lambda expression
-                        let fn_exp:LisperExp = lambda.last().unwrap().clone();
-
-                        // Evalute lambda function call in new env and return the result
-                        Ok(eval(fn_exp, &mut sub_env)?)
-                    }
-                },
-                _ => Err(LisperErr::Reason("Error, function not found.".to_string()))
-            }
+        let source = "(fn inner x (+ x missing))\n(fn outer x (+ 1 (inner x)))\n(outer 1)";
+        let tokens = tokenize(source.to_string());
+        match eval_program(parse_program(&tokens)?, &mut env) {
+            Err(e) => {
+                assert!(matches!(e.kind(), LisperErr::UnboundSymbol(name) if name == "missing"));
+                assert_eq!(e.stack(), ["inner", "outer"]);
+                assert_eq!(
+                    e.render(source),
+                    "Error = Unbound variable `missing`. (line 1, column 13)\n1 | (fn inner x (+ x missing))\n  |             ^^^^^^^^^^^^^\n    at inner\n    at outer"
+                );
+            },
+            Ok(_) => assert!(false)
+        }
+
+        // Tail calls replace the function they were made from, and recursion is collapsed
+        eval_source("(fn down n (if (< n 1) missing (+ 0 (down (- n 1)))))", &mut env)?;
+        match eval_source("(down 3)", &mut env) {
+            Err(e) => {
+                assert_eq!(e.stack().len(), 4);
+                assert!(e.to_string().ends_with("\n    at down (4 times)"));
+            },
+            Ok(_) => assert!(false)
         }
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
     #[test]
-    fn tokenize_expr() {
+    fn eval_throw() {
         use super::*;
+        use crate::env::create_default_env;
 
-        assert_eq!(tokenize("(+ 1 1)".to_string()), ["(", "+", "1", "1", ")"]);
+        let mut env = create_default_env();
+
+        match eval_source("(error \"bad input\")", &mut env) {
+            Err(e) => {
+                assert!(e.to_string().starts_with("Error, bad input"));
+                assert_eq!(e.kind_name(), "user");
+            },
+            Ok(_) => assert!(false)
+        }
+        match eval_source("(throw (+ 1 2))", &mut env) {
+            Err(e) => assert!(matches!(e.kind(), LisperErr::User(LisperExp::Int(3)))),
+            Ok(_) => assert!(false)
+        }
+        assert!(eval_source("(throw)", &mut env).is_err());
     }
 
     #[test]
-    fn parse_expr() -> Result<(),  Box<dyn std::error::Error>> {
-        use super::*;
-        
-        // Create a set of valid tokens that we can parse
-        let mock_tokens = ["(".to_string(), "+".to_string(), "1".to_string(), "1".to_string(), ")".to_string()];
-        
-        // Parse mock tockens, expect back a LisperExp::List
-        let (parsed_tokens, _) = parse(&mock_tokens[..])?;
-        match parsed_tokens {
-            LisperExp::List(list) => assert_eq!(list.len(), 3),
-            _ => assert!(false)
-        }
+    fn eval_try() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        assert_eq!(eval_source("(try (+ 1 2) (catch e 0))", &mut env)?, "3");
+        assert_eq!(eval_source("(try (error \"bad\") (catch e e))", &mut env)?, "\"bad\"");
+        // Errors that weren't raised by Lisper code are caught as their message
+        assert_eq!(eval_source("(try (+ 1 missing) (catch e e))", &mut env)?, "\"Unbound variable `missing`.\"");
+
+        // Catch clauses can dispatch on the kind of error
+        eval_source("(fn check x (try (if x missing (throw 41)) (catch :unbound-symbol e \"unbound\") (catch :user e (+ e 1))))", &mut env)?;
+        assert_eq!(eval_source("(check true)", &mut env)?, "\"unbound\"");
+        assert_eq!(eval_source("(check false)", &mut env)?, "42");
+        assert!(eval_source("(check \"not a condition\")", &mut env).is_err());
+
+        // including errors from builtin functions
+        assert_eq!(eval_source("(try (nth '(1 2) 5) (catch :type e :type) (catch :index-out-of-range e e))", &mut env)?,
+                   "\"Index error, index 5 is out of range for a list of length 2.\"");
+        assert_eq!(eval_source("(try (assoc [1] 3 0) (catch :index-out-of-range e :index))", &mut env)?, ":index");
+        assert_eq!(eval_source("(try (subvec [1] 0 2) (catch :index-out-of-range e :index))", &mut env)?, ":index");
+        assert_eq!(eval_source("(try (range 0 1 0) (catch :type e :type))", &mut env)?, ":type");
+        assert_eq!(eval_source("(try (hash-map :a) (catch :arity e e))", &mut env)?, "\"Arity error, hash-map expects an even number of arguments, got 1.\"");
+        assert_eq!(eval_source("(try (assoc {} :a 1 :b) (catch :arity e e))", &mut env)?, "\"Arity error, assoc expects an odd number of arguments, got 4.\"");
+
+        // finally always runs, after the body or catch clause
+        eval_source("(def cleaned 0)", &mut env)?;
+        assert_eq!(eval_source("(try (error 1) (catch e 2) (finally (set! cleaned (+ cleaned 1))))", &mut env)?, "2");
+        assert!(eval_source("(try (error 1) (catch :type e 2) (finally (set! cleaned (+ cleaned 1))))", &mut env).is_err());
+        assert_eq!(eval_source("cleaned", &mut env)?, "2");
+
+        // Failures can be collected rather than stopping at the first one
+        eval_source("(def failures 0)", &mut env)?;
+        eval_source("(fn validate x (try (if (< x 0) (error \"negative\") x) (catch e (set! failures (+ failures 1)))))", &mut env)?;
+        eval_source("(validate -1) (validate 2) (validate -3)", &mut env)?;
+        assert_eq!(eval_source("failures", &mut env)?, "2");
+
+        assert!(eval_source("(try (catch e 1))", &mut env).is_err());
+        assert!(eval_source("(try 1 (finally 2) (catch e 3))", &mut env).is_err());
+        assert!(eval_source("(try 1 (catch :user e))", &mut env).is_err());
+
         Ok(())
     }
 
     #[test]
-    fn parse_number_expr() -> Result<(),  Box<dyn std::error::Error>> {
-        use super::*;
-        
-        // Create a set of valid tokens that we can parse
-        let mock_token = "99";
-        
-        // Parse mock tockens, expect back a LisperExp::List
-        match parse_token(&mock_token) {
-            LisperExp::Number(num) => assert_eq!(num, 99.0),
-            _ => assert!(false)
+    fn eval_higher_order_functions() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // Builtins and lambdas can both be passed
+        assert_eq!(eval_source("(map (lambda (x) (* x x)) '(1 2 3))", &mut env)?, "(1 4 9)");
+        assert_eq!(eval_source("(map + '(1 2 3) '(10 20))", &mut env)?, "(11 22)");
+        assert_eq!(eval_source("(filter (lambda (x) (> x 1)) '(1 2 3))", &mut env)?, "(2 3)");
+        assert_eq!(eval_source("(reduce + '(1 2 3 4))", &mut env)?, "10");
+        assert_eq!(eval_source("(fold (lambda (acc x) (- acc x)) 10 '(1 2))", &mut env)?, "7");
+        assert_eq!(eval_source("(apply + 1 '(2 3))", &mut env)?, "6");
+        assert_eq!(eval_source("(any (lambda (x) (> x 2)) '(1 2 3))", &mut env)?, "true");
+        assert_eq!(eval_source("(every (lambda (x) (> x 2)) '(1 2 3))", &mut env)?, "false");
+
+        eval_source("(def total 0)", &mut env)?;
+        eval_source("(for-each (lambda (x) (set! total (+ total x))) '(1 2 3))", &mut env)?;
+        assert_eq!(eval_source("total", &mut env)?, "6");
+
+        // Sorting is stable, and works on number or string keys
+        eval_source("(fn second pair (apply (lambda (a b) b) pair))", &mut env)?;
+        assert_eq!(eval_source("(sort-by second '((a 3) (b 1) (c 3) (d 2)))", &mut env)?, "((b 1) (d 2) (a 3) (c 3))");
+        assert_eq!(eval_source("(sort-by (lambda (s) s) '(\"pear\" \"apple\"))", &mut env)?, "(\"apple\" \"pear\")");
+
+        // Errors from the function passed in are returned, with it in the call stack
+        eval_source("(fn check x (if (< x 3) x (error \"too big\")))", &mut env)?;
+        match eval_source("(map check '(1 2 3))", &mut env) {
+            Err(e) => {
+                assert!(e.to_string().starts_with("Error, too big"));
+                assert_eq!(e.stack(), ["check"]);
+            },
+            Ok(_) => assert!(false)
         }
+        assert!(eval_source("(map check 1)", &mut env).is_err());
+        assert!(eval_source("(reduce + '())", &mut env).is_err());
+        assert!(eval_source("(filter (lambda (x) \"yes\") '(1))", &mut env).is_err());
+        assert!(eval_source("(sort-by (lambda (x) x) '(1 \"a\"))", &mut env).is_err());
+
         Ok(())
     }
 
     #[test]
-    fn parse_symbol_expr() -> Result<(),  Box<dyn std::error::Error>> {
+    fn eval_list_functions() -> Result<(), Box<dyn std::error::Error>> {
         use super::*;
-        
-        // Create a set of valid tokens that we can parse
-        let mock_token = "+";
-        
-        // Parse mock tockens, expect back a LisperExp::List
-        match parse_token(&mock_token) {
-            LisperExp::Symbol(sym) => assert_eq!(sym.to_string(), "+".to_string()),
-            _ => assert!(false)
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // Lists are built and taken apart, and print the way they are written
+        assert_eq!(eval_source("(list 1 \"two\" (list 3))", &mut env)?, "(1 \"two\" (3))");
+        assert_eq!(eval_source("(cons 1 '(2 3))", &mut env)?, "(1 2 3)");
+        assert_eq!(eval_source("(car '(1 2 3))", &mut env)?, "1");
+        assert_eq!(eval_source("(rest '(1 2 3))", &mut env)?, "(2 3)");
+        assert_eq!(eval_source("(cdr '(1))", &mut env)?, "()");
+        assert_eq!(eval_source("(nth '(a b c) 1)", &mut env)?, "b");
+        assert_eq!(eval_source("(length '(1 2 3))", &mut env)?, "3");
+        assert_eq!(eval_source("(length \"h\u{e9}llo\")", &mut env)?, "5");
+        assert_eq!(eval_source("(append '(1) '() '(2 3))", &mut env)?, "(1 2 3)");
+        assert_eq!(eval_source("(reverse '(1 2 3))", &mut env)?, "(3 2 1)");
+        assert_eq!(eval_source("(last '(1 2 3))", &mut env)?, "3");
+        assert_eq!(eval_source("(take 2 '(1 2 3))", &mut env)?, "(1 2)");
+        assert_eq!(eval_source("(drop 5 '(1 2 3))", &mut env)?, "()");
+        assert_eq!(eval_source("(range 4)", &mut env)?, "(0 1 2 3)");
+        assert_eq!(eval_source("(range 10 0 -4)", &mut env)?, "(10 6 2)");
+        assert_eq!(eval_source("(range 3 1)", &mut env)?, "()");
+        assert_eq!(eval_source("(range 9223372036854775807 9223372036854775809)", &mut env)?, "(9223372036854775807 9223372036854775808)");
+        assert_eq!(eval_source("(range 100000000000000000000 99999999999999999997 -2)", &mut env)?, "(100000000000000000000 99999999999999999998)");
+        assert_eq!(eval_source("(range 0 1 1/3)", &mut env)?, "(0 1/3 2/3)");
+
+        // nil and () are the empty list
+        assert_eq!(eval_source("nil", &mut env)?, "()");
+        assert_eq!(eval_source("(= () nil '())", &mut env)?, "true");
+        assert_eq!(eval_source("(empty? (cdr '(1)))", &mut env)?, "true");
+        assert_eq!(eval_source("(cons 1 nil)", &mut env)?, "(1)");
+
+        // Functions print as the lambda they are equivalent to
+        assert_eq!(eval_source("(fn add (&optional (n 1)) (+ n 1)) add", &mut env)?, "(lambda (&optional (n 1)) (+ n 1))");
+        // and built-in functions print as their name
+        assert_eq!(eval_source("+", &mut env)?, "+");
+        assert_eq!(eval_source("(list car cdr)", &mut env)?, "(car cdr)");
+
+        for source in ["(car '())", "(cdr nil)", "(last '())", "(nth '(1 2) 2)", "(nth '(1 2) -1)", "(nth '(1 2) 4294967296)", "(take -1 '(1))", "(cons 1 2)",
+                       "(take 1.5 '(1 2))", "(range 0 10 0)", "(length 5)"] {
+            assert!(eval_source(source, &mut env).is_err(), "{}", source);
         }
+        match eval_source("(range 1e12)", &mut env) {
+            Err(e) => assert!(matches!(e.kind(), LisperErr::ResourceLimit(_))),
+            Ok(_) => assert!(false)
+        }
+
         Ok(())
     }
 
     #[test]
-    fn parse_bool_expr() -> Result<(),  Box<dyn std::error::Error>> {
+    fn parse_map_literal() -> Result<(), Box<dyn std::error::Error>> {
         use super::*;
-        
-        // Create a set of valid tokens that we can parse
-        let mock_token = "true";
-        
-        // Parse mock tockens, expect back a LisperExp::List
-        match parse_token(&mock_token) {
-            LisperExp::Bool(b) => assert!(b),
+
+        // Literals keep their keys and values in the order they were written
+        let (parsed, _) = parse(&tokenize("{:b (1 2) :a \"x\" :b 3}".to_string()))?;
+        match &parsed {
+            LisperExp::MapLiteral(pairs) => assert_eq!(pairs[1], (LisperExp::Keyword("a".to_string()), LisperExp::Str("x".to_string()))),
             _ => assert!(false)
         }
+        assert_eq!(parsed.to_string(), "{:b (1 2) :a \"x\" :b 3}");
+        assert!(parse(&tokenize(parsed.to_string()))?.0 == parsed);
+
+        // Quoted, they are maps, which print sorted by key as a literal that reads back as an equal map
+        let quoted = datum(&parsed);
+        assert_eq!(quoted.to_string(), "{:a \"x\" :b 3}");
+        assert!(datum(&parse(&tokenize(quoted.to_string()))?.0) == quoted);
+
+        for (source, message) in [("{:a}", "map needs a value for every key."), ("{:a 1", "map is missing a closing }."), ("(1 2}", "found unexpected }.")] {
+            match parse(&tokenize(source.to_string())) {
+                Err(e) => assert_eq!(e.kind().to_string(), format!("Syntax error, {}", message)),
+                Ok(_) => assert!(false)
+            }
+        }
         Ok(())
     }
 
     #[test]
-    fn parse_expr_complex() -> Result<(),  Box<dyn std::error::Error>> {
-        use super::*;
-        
-        // Create a set of valid tokens that we can parse
-        let mock_tokens = ["(".to_string(), "+".to_string(), "1".to_string(), ")".to_string(), "(".to_string(), "*".to_string(), "2".to_string(), "2".to_string(), ")".to_string()];
-        
-        // Parse mock tockens, expect back a LisperExp::List
-        let (parsed_tokens, _) = parse(&mock_tokens[..])?;
-        match parsed_tokens {
-            LisperExp::List(list) => assert_eq!(list.len(), 2),
-            _ => assert!(false)
+    fn eval_map_functions() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::env::create_default_env;
+
+        let mut env = create_default_env();
+
+        // Keys and values of literals are evaluated
+        eval_source("(def n 2) (def m {:a 1 :b (+ n 1)})", &mut env)?;
+        assert_eq!(eval_source("m", &mut env)?, "{:a 1 :b 3}");
+        assert_eq!(eval_source("{\"k\" {n '(x y)}}", &mut env)?, "{\"k\" {2 (x y)}}");
+        assert_eq!(eval_source("`{:a ,n}", &mut env)?, "{:a 2}");
+        assert_eq!(eval_source("(hash-map :x 1)", &mut env)?, "{:x 1}");
+
+        // in the order they are written, and the last of any equal keys is kept
+        eval_source("(def order '()) (fn note x (set! order (cons x order)) x)", &mut env)?;
+        assert_eq!(eval_source("{(note :a) (note 1) (note :b) (note 2) (note :c) (note 3)}", &mut env)?, "{:a 1 :b 2 :c 3}");
+        assert_eq!(eval_source("order", &mut env)?, "(3 :c 2 :b 1 :a)");
+        assert_eq!(eval_source("(def a 1) (def b 1) {a :from-a b :from-b}", &mut env)?, "{1 :from-b}");
+        assert_eq!(eval_source("(length {(gensym) 1 (gensym) 2})", &mut env)?, "2");
+        assert_eq!(eval_source("'{:a 1 :a 2}", &mut env)?, "{:a 2}");
+        eval_source("(set! order '())", &mut env)?;
+        assert_eq!(eval_source("`{,(note :x) ,(note 1) :y ,(note 2)}", &mut env)?, "{:x 1 :y 2}");
+        assert_eq!(eval_source("order", &mut env)?, "(2 1 :x)");
+
+        assert_eq!(eval_source("(get m :b)", &mut env)?, "3");
+        assert_eq!(eval_source("(get m :z)", &mut env)?, "()");
+        assert_eq!(eval_source("(get m :z 0)", &mut env)?, "0");
+        assert_eq!(eval_source("(assoc m :c 4 :a 0)", &mut env)?, "{:a 0 :b 3 :c 4}");
+        assert_eq!(eval_source("(dissoc m :a :z)", &mut env)?, "{:b 3}");
+        assert_eq!(eval_source("(keys m)", &mut env)?, "(:a :b)");
+        assert_eq!(eval_source("(vals m)", &mut env)?, "(1 3)");
+        assert_eq!(eval_source("(contains? m :a)", &mut env)?, "true");
+        assert_eq!(eval_source("(merge m {:b 5} {})", &mut env)?, "{:a 1 :b 5}");
+        assert_eq!(eval_source("(update m :a + 10)", &mut env)?, "{:a 11 :b 3}");
+        assert_eq!(eval_source("(length m)", &mut env)?, "2");
+        // The original map isn't changed
+        assert_eq!(eval_source("m", &mut env)?, "{:a 1 :b 3}");
+
+        // Maps are equal when their entries are, whatever order they were written in
+        assert_eq!(eval_source("(= {:a 1 :b 2} {:b 2 :a 1})", &mut env)?, "true");
+        assert_eq!(eval_source("(= {:a 1} {:a 2})", &mut env)?, "false");
+        assert_eq!(eval_source("(get {'(1 2) :list 0 :zero} '(1 2))", &mut env)?, ":list");
+        assert_eq!(eval_source("(get {0 :zero} -0)", &mut env)?, ":zero");
+
+        // Higher order functions iterate over entries as (key value) lists
+        assert_eq!(eval_source("(map (lambda (entry) (car entry)) m)", &mut env)?, "(:a :b)");
+        assert_eq!(eval_source("(fold (lambda (acc entry) (+ acc (nth entry 1))) 0 m)", &mut env)?, "4");
+        assert_eq!(eval_source("(filter (lambda (entry) (> (last entry) 1)) m)", &mut env)?, "((:b 3))");
+
+        for source in ["(get '(1) 0)", "(assoc m :a)", "(merge m 1)", "(keys '())", "(update m :a car)"] {
+            assert!(eval_source(source, &mut env).is_err(), "{}", source);
         }
+
         Ok(())
     }
 
     #[test]
-    fn eval_def() -> Result<(), Box<dyn std::error::Error>> {
+    fn eval_vector_functions() -> Result<(), Box<dyn std::error::Error>> {
         use super::*;
         use crate::env::create_default_env;
 
-        // Test if eval handles def
-        // Format: (def variable_name[as LisperExp::Symbol] (value[as LisperExp]))
+        let mut env = create_default_env();
 
-        let def_exp:LisperExp = LisperExp::List(vec![
-            LisperExp::Symbol("def".to_string()),
-            LisperExp::Symbol("a".to_string()),
-            LisperExp::Number(1.0)
-        ]);
+        // Items of literals are evaluated, and vectors print the way they are written
+        eval_source("(def n 2) (def v [1 n (+ n 1)])", &mut env)?;
+        assert_eq!(eval_source("v", &mut env)?, "[1 2 3]");
+        assert_eq!(eval_source("'[a (b c)]", &mut env)?, "[a (b c)]");
+        assert_eq!(eval_source("`[0 ,@v ,n]", &mut env)?, "[0 1 2 3 2]");
+        assert_eq!(eval_source("(vector 1 \"a\")", &mut env)?, "[1 \"a\"]");
+        assert_eq!(eval_source("(vec '(1 2))", &mut env)?, "[1 2]");
+        assert_eq!(eval_source("(vec {:a 1})", &mut env)?, "[(:a 1)]");
 
-        let add_exp:LisperExp = LisperExp::List(vec![
-            LisperExp::Symbol("+".to_string()),
-            LisperExp::Symbol("a".to_string()),
-            LisperExp::Number(1.0)
-        ]);
-        
-        let mut env:LisperEnv = create_default_env();
+        assert_eq!(eval_source("(nth v 2)", &mut env)?, "3");
+        assert_eq!(eval_source("(get v 0)", &mut env)?, "1");
+        assert_eq!(eval_source("(get v 5 :none)", &mut env)?, ":none");
+        assert_eq!(eval_source("(contains? v 2)", &mut env)?, "true");
+        assert_eq!(eval_source("(contains? v 3)", &mut env)?, "false");
+        assert_eq!(eval_source("(conj v 4 5)", &mut env)?, "[1 2 3 4 5]");
+        assert_eq!(eval_source("(conj '(2 3) 1 0)", &mut env)?, "(0 1 2 3)");
+        assert_eq!(eval_source("(conj {:a 1} [:b 2])", &mut env)?, "{:a 1 :b 2}");
+        assert_eq!(eval_source("(assoc v 0 :x 3 4)", &mut env)?, "[:x 2 3 4]");
+        assert_eq!(eval_source("(subvec v 1)", &mut env)?, "[2 3]");
+        assert_eq!(eval_source("(subvec v 1 2)", &mut env)?, "[2]");
+        assert_eq!(eval_source("v", &mut env)?, "[1 2 3]");
 
-        if let LisperExp::Number(_) = eval(def_exp, &mut env)? {
-            if let LisperExp::Number(res) = eval(add_exp, &mut env)? {
-                assert_eq!(2.0, res);
-            } else {
-                assert!(false);
-            }
-        } else {
-            assert!(false);
+        // List and higher order functions take vectors, and return lists
+        assert_eq!(eval_source("(length v)", &mut env)?, "3");
+        assert_eq!(eval_source("(rest v)", &mut env)?, "(2 3)");
+        assert_eq!(eval_source("(map (lambda (x) (* x 2)) v)", &mut env)?, "(2 4 6)");
+        assert_eq!(eval_source("(apply + v)", &mut env)?, "6");
+
+        // Vectors are equal to vectors with equal items, but not to lists
+        assert_eq!(eval_source("(= v [1 2 3])", &mut env)?, "true");
+        assert_eq!(eval_source("(= v '(1 2 3))", &mut env)?, "false");
+        assert_eq!(eval_source("(get {[1 2] :found} [1 2])", &mut env)?, ":found");
+
+        // Vector keys sort item by item
+        assert_eq!(eval_source("(sort-by (lambda (x) x) '([2 \"a\"] [1 \"b\"] [1 \"a\"] [1]))", &mut env)?, "([1] [1 \"a\"] [1 \"b\"] [2 \"a\"])");
+
+        for source in ["(nth v 3)", "(assoc v 5 0)", "(assoc v 0)", "(subvec v 2 1)", "(subvec v 0 4)", "(subvec '(1) 0)",
+                       "(conj {} [1])", "(conj 1 2)", "(sort-by (lambda (x) x) '([1] [\"a\"]))"] {
+            assert!(eval_source(source, &mut env).is_err(), "{}", source);
+        }
+        match parse(&tokenize("[1 2)".to_string())) {
+            Err(e) => assert_eq!(e.kind().to_string(), "Syntax error, found unexpected )."),
+            Ok(_) => assert!(false)
         }
 
         Ok(())
     }
 
     #[test]
-    fn eval_if() -> Result<(), Box<dyn std::error::Error>> {
+    fn eval_keywords() -> Result<(), Box<dyn std::error::Error>> {
         use super::*;
         use crate::env::create_default_env;
 
-        // Test if eval handles if
-        // Format: (if (if expression[as LisperExp]) (true expression[as LisperExp]) (false expression[as LisperExp]))
+        let mut env = create_default_env();
 
-        let if_exp:LisperExp = LisperExp::List(vec![
-            LisperExp::Symbol("<".to_string()),
-            LisperExp::Number(1.0),
-            LisperExp::Number(0.0),
-        ]);
-        let if_stmnt:LisperExp = LisperExp::List(vec![
-            LisperExp::Symbol("if".to_string()),
-            if_exp,
-            LisperExp::Number(1.0),
-            LisperExp::Number(2.0),
-        ]);
-        
-        let mut env:LisperEnv = create_default_env();
+        // Keywords are read as their own type, and evaluate to themselves
+        let (parsed, _) = parse(&tokenize(":name".to_string()))?;
+        assert!(parsed == LisperExp::Keyword("name".to_string()));
+        assert!(parse(&tokenize(":".to_string()))?.0 == LisperExp::Symbol(":".to_string()));
+        assert_eq!(eval_source(":red", &mut env)?, ":red");
+        assert_eq!(eval_source("'(:a b)", &mut env)?, "(:a b)");
+        assert_eq!(eval_source("(= :red :red)", &mut env)?, "true");
+        assert_eq!(eval_source("(= :red :blue)", &mut env)?, "false");
+        assert_eq!(eval_source("(= :red 'red)", &mut env)?, "false");
+        assert_eq!(eval_source("(case :blue ((:red) 1) ((:blue) 2))", &mut env)?, "2");
 
-        if let LisperExp::Number(res) = eval(if_stmnt, &mut env)? {
-            assert_eq!(2.0, res);
-        } else {
-            assert!(false);
+        // Keywords can be called on maps to get their value
+        eval_source("(def person {:name \"Ada\" :age 36})", &mut env)?;
+        assert_eq!(eval_source("(:name person)", &mut env)?, "\"Ada\"");
+        assert_eq!(eval_source("(:email person)", &mut env)?, "()");
+        assert_eq!(eval_source("(:email person \"none\")", &mut env)?, "\"none\"");
+        assert_eq!(eval_source("(map :age (list person {:age 2}))", &mut env)?, "(36 2)");
+
+        match eval_source("(:name)", &mut env) {
+            Err(e) => assert!(matches!(e.kind(), LisperErr::Arity { name, got: 0, .. } if name == ":name")),
+            Ok(_) => assert!(false)
         }
+        assert!(eval_source("(:name '(1 2))", &mut env).is_err());
 
         Ok(())
     }
 
     #[test]
-    fn eval_fn() -> Result<(), Box<dyn std::error::Error>> {
-        use super::*;
+    fn eval_numeric_tower() -> Result<(), Box<dyn std::error::Error>> {
         use crate::env::create_default_env;
 
-        // Test if eval handles fn
-        // Format: (fn function_name[as string] (arguments[as LisperExp]) (function[as LisperExp]))
+        let mut env = create_default_env();
 
-        let def_fn_one_arg_exp:LisperExp = LisperExp::List(vec![
-            LisperExp::Symbol("fn".to_string()),
-            LisperExp::Symbol("add-fn".to_string()),
-            LisperExp::Symbol("a".to_string()),
-            LisperExp::List(vec![
-                LisperExp::Symbol("+".to_string()),
-                LisperExp::Symbol("a".to_string()),
-                LisperExp::Number(1.0)
-            ])
-        ]);
+        // Integers are exact, and print without a decimal point, unlike floats
+        assert_eq!(eval_source("(+ 1 2)", &mut env)?, "3");
+        assert_eq!(eval_source("(+ 1.5 1.5)", &mut env)?, "3.0");
+        assert_eq!(eval_source("(* 2 0.5)", &mut env)?, "1.0");
+        assert_eq!(eval_source("(/ 6 3)", &mut env)?, "2");
+        assert_eq!(eval_source("(/ 1 4)", &mut env)?, "1/4");
+        assert_eq!(eval_source("(+ 9007199254740993 0)", &mut env)?, "9007199254740993");
 
-        let fn_call_one_arg_fn_exp:LisperExp = LisperExp::List(vec![
-            LisperExp::Symbol("add-fn".to_string()),
-            LisperExp::Number(1.0)
-        ]);
-        
-        let mut env:LisperEnv = create_default_env();
+        // Integers that overflow an i64 become big integers, and come back when they fit
+        assert_eq!(eval_source("(* 9223372036854775807 2)", &mut env)?, "18446744073709551614");
+        assert_eq!(eval_source("(- -9223372036854775807 2)", &mut env)?, "-9223372036854775809");
+        assert_eq!(eval_source("(- 18446744073709551614 9223372036854775807 9223372036854775807)", &mut env)?, "0");
+        eval_source("(fn fact n (if (< n 2) 1 (* n (fact (- n 1)))))", &mut env)?;
+        assert_eq!(eval_source("(fact 25)", &mut env)?, "15511210043330985984000000");
+        assert_eq!(eval_source("(quot (fact 25) (fact 23))", &mut env)?, "600");
+        assert_eq!(eval_source("(+ (fact 25) 0.5)", &mut env)?, "15511210043330986000000000.0");
 
-        if let LisperExp::Bool(_) = eval(def_fn_one_arg_exp, &mut env)? {
-            if let LisperExp::Number(res) = eval(fn_call_one_arg_fn_exp, &mut env)? {
-                assert_eq!(2.0, res);
-            } else {
-                assert!(false);
-            }
-        } else {
-            assert!(false);
+        // Integer division rounds towards zero, and rem has the sign of the number divided
+        assert_eq!(eval_source("(quot 7 2)", &mut env)?, "3");
+        assert_eq!(eval_source("(quot -7 2)", &mut env)?, "-3");
+        assert_eq!(eval_source("(rem -7 2)", &mut env)?, "-1");
+        assert_eq!(eval_source("(% 10 3)", &mut env)?, "1");
+        assert_eq!(eval_source("(quot 7.5 2)", &mut env)?, "3.0");
+
+        // Comparisons and = work by value across integers and floats
+        assert_eq!(eval_source("(= 1 1.0)", &mut env)?, "true");
+        assert_eq!(eval_source("(case 1 ((1.0) :one) (else :other))", &mut env)?, ":one");
+        assert_eq!(eval_source("(case 2.0 (2 :two) (else :other))", &mut env)?, ":two");
+        // but only as numbers themselves, inside lists, vectors and maps exact and float numbers differ
+        assert_eq!(eval_source("(= [1] [1.0])", &mut env)?, "false");
+        assert_eq!(eval_source("(= '(1) '(1.0))", &mut env)?, "false");
+        assert_eq!(eval_source("(= [1] [1])", &mut env)?, "true");
+        assert_eq!(eval_source("(get {1 :x} 1.0)", &mut env)?, "()");
+        assert_eq!(eval_source("(get {1 :x} 1)", &mut env)?, ":x");
+        assert_eq!(eval_source("(< 1 1.5 2 (fact 25))", &mut env)?, "true");
+        assert_eq!(eval_source("(length '(1 2))", &mut env)?, "2");
+        assert_eq!(eval_source("(range 0 1 0.5)", &mut env)?, "(0.0 0.5)");
+
+        for source in ["(quot 1 0)", "(rem 1 0)", "(/ (fact 25) 0)", "(quot 1)"] {
+            assert!(eval_source(source, &mut env).is_err(), "{}", source);
         }
 
         Ok(())
     }
 
     #[test]
-    fn eval_fn_multiple_arg() -> Result<(), Box<dyn std::error::Error>> {
+    fn eval_rationals() -> Result<(), Box<dyn std::error::Error>> {
         use super::*;
         use crate::env::create_default_env;
 
-        // Test if eval handles fn
-        // Format: (fn function_name[as string] (arguments[as LisperExp]) (function[as LisperExp]))
+        let mut env = create_default_env();
 
-        let def_fn_multiple_arg_exp:LisperExp = LisperExp::List(vec![
-            LisperExp::Symbol("fn".to_string()),
-            LisperExp::Symbol("multipe-args-fn".to_string()),
-            LisperExp::Symbol("a".to_string()),
-            LisperExp::Symbol("b".to_string()),
-            LisperExp::Symbol("c".to_string()),
-            LisperExp::Symbol("d".to_string()),
-            LisperExp::Symbol("e".to_string()),
-            LisperExp::List(vec![
-                LisperExp::Symbol("*".to_string()),
-                LisperExp::Symbol("a".to_string()),
-                LisperExp::Symbol("b".to_string()),
-                LisperExp::Symbol("c".to_string()),
-                LisperExp::Symbol("d".to_string()),
-                LisperExp::Symbol("e".to_string())
-            ])
-        ]);
+        // Fractions are read in lowest terms, and whole ones become integers
+        assert_eq!(eval_source("1/3", &mut env)?, "1/3");
+        assert_eq!(eval_source("-6/4", &mut env)?, "-3/2");
+        assert_eq!(eval_source("4/2", &mut env)?, "2");
+        assert_eq!(eval_source("'(1/2 a/b 1/-2)", &mut env)?, "(1/2 a/b 1/-2)");
 
-        let fn_call_multiple_arg_fn_exp:LisperExp = LisperExp::List(vec![
-            LisperExp::Symbol("multipe-args-fn".to_string()),
-            LisperExp::Number(1.0),
-            LisperExp::Number(2.0),
-            LisperExp::Number(3.0),
-            LisperExp::Number(4.0),
-            LisperExp::Number(5.0)
-        ]);
-        
-        let mut env:LisperEnv = create_default_env();
+        // Dividing integers stays exact, and so does arithmetic on fractions
+        assert_eq!(eval_source("(/ 1 3)", &mut env)?, "1/3");
+        assert_eq!(eval_source("(+ 1/3 1/6)", &mut env)?, "1/2");
+        assert_eq!(eval_source("(* 1/3 3)", &mut env)?, "1");
+        assert_eq!(eval_source("(- 1/3)", &mut env)?, "-1/3");
+        assert_eq!(eval_source("(/ 2/3)", &mut env)?, "3/2");
+        assert_eq!(eval_source("(+ 1/3 0.5)", &mut env)?, "0.8333333333333333");
+        assert_eq!(eval_source("(/ 1 99999999999999999999)", &mut env)?, "1/99999999999999999999");
+        assert_eq!(eval_source("(quot 7/2 1)", &mut env)?, "3");
+        assert_eq!(eval_source("(rem 7/2 1)", &mut env)?, "1/2");
 
-        if let LisperExp::Bool(_) = eval(def_fn_multiple_arg_exp, &mut env)? {
-            if let LisperExp::Number(res) = eval(fn_call_multiple_arg_fn_exp, &mut env)? {
-                assert_eq!(120.0, res);
-            } else {
-                assert!(false);
-            }
-        } else {
-            assert!(false);
+        // Fractions compare by value with other numbers
+        assert_eq!(eval_source("(< 1/3 0.34 1/2 1)", &mut env)?, "true");
+        assert_eq!(eval_source("(= 1/2 0.5 2/4)", &mut env)?, "true");
+        assert_eq!(eval_source("(= (+ 1/10 2/10) 3/10)", &mut env)?, "true");
+
+        assert_eq!(eval_source("(numerator 6/4)", &mut env)?, "3");
+        assert_eq!(eval_source("(denominator 6/4)", &mut env)?, "2");
+        assert_eq!(eval_source("(denominator 5)", &mut env)?, "1");
+        assert_eq!(eval_source("(exact->inexact 1/4)", &mut env)?, "0.25");
+        // Fractions with parts too big for a float still convert to the float nearest their value
+        let big = format!("{}1", "1".repeat(320));
+        eval_source(&format!("(def near-one (/ {}2 {}1))", big, big), &mut env)?;
+        assert_eq!(eval_source("(exact->inexact near-one)", &mut env)?, "1.0");
+        assert_eq!(eval_source("(+ near-one 0.5)", &mut env)?, "1.5");
+        assert_eq!(eval_source("(< near-one 2.0)", &mut env)?, "true");
+        assert_eq!(eval_source(&format!("(exact->inexact (/ -1 {}{}))", big, big), &mut env)?, "-0.0");
+        assert_eq!(eval_source(&format!("(exact->inexact (/ {} 3))", big), &mut env)?, "inf");
+        assert_eq!(eval_source("(rationalize 0.1)", &mut env)?, "1/10");
+        assert_eq!(eval_source("(rationalize 1.5)", &mut env)?, "3/2");
+        assert_eq!(eval_source("(rationalize 0.333 1/100)", &mut env)?, "1/3");
+        assert_eq!(eval_source("(rationalize -0.333 1/100)", &mut env)?, "-1/3");
+        assert_eq!(eval_source("(rationalize 3/10 1/10)", &mut env)?, "1/3");
+
+        for source in ["(numerator 0.5)", "(rationalize (/ 1.0 0.0))", "(/ 1/2 0)"] {
+            assert!(eval_source(source, &mut env).is_err(), "{}", source);
+        }
+        match parse(&tokenize("1/0".to_string())) {
+            Err(e) => assert_eq!(e.kind().to_string(), "Syntax error, 1/0 has a denominator of zero."),
+            Ok(_) => assert!(false)
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1,251 +1,997 @@
+use std::borrow::Cow;
+use std::cell::{ Cell, RefCell };
 use std::collections::HashMap;
-use crate::exp::LisperExp;
-//  Represents the context where a Lisp expression executes
+use std::rc::{ Rc, Weak };
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::sync::atomic::{self, AtomicUsize};
+use crate::exp::{ map_entries, LisperExp, LisperNative };
+use crate::core::{ apply, is_true, LisperErr };
+use crate::num::{ simplest_between, Num };
+//  Represents the context where a Lisp expression executes. Each env is a scope
+//  chained to the one it was created from, and cloning an env shares the scope
+//  rather than copying it.
 #[derive(Clone)]
 pub struct LisperEnv {
-    pub data: HashMap<String, LisperExp>
+    scope: Rc<RefCell<LisperScope>>,
+    // Shared by the envs the host application holds, and left out of the envs kept inside
+    // Lisper values, such as the scope a function was defined in
+    owner: Option<Rc<EnvOwner>>
+}
+
+// The bindings of a single scope, and the scope lookups fall back to
+struct LisperScope {
+    data: HashMap<String, LisperExp>,
+    parent: Option<LisperEnv>,
+    // Every scope of the env this one was created in
+    scopes: Rc<ScopeList>
+}
+
+// The scopes created in an env. Functions are stored in the scopes they capture, so the two
+// keep each other alive, and counting references would never free them. Instead, every
+// scope is cleared once the host application drops the env.
+#[derive(Default)]
+struct ScopeList {
+    scopes: RefCell<Vec<Weak<RefCell<LisperScope>>>>,
+    // How many scopes were still alive when the freed ones were last removed
+    live: Cell<usize>
+}
+
+impl ScopeList {
+    fn add(&self, scope: &Rc<RefCell<LisperScope>>) {
+        let mut scopes = self.scopes.borrow_mut();
+        // Forget the scopes that have been freed, whenever the list doubles in size
+        if scopes.len() >= 2 * self.live.get().max(32) {
+            scopes.retain(|scope| scope.strong_count() > 0);
+            self.live.set(scopes.len());
+        }
+        scopes.push(Rc::downgrade(scope));
+    }
+}
+
+// Dropped along with the last env the host application holds, which frees everything in it
+struct EnvOwner {
+    scopes: Rc<ScopeList>
+}
+
+impl Drop for EnvOwner {
+    fn drop(&mut self) {
+        let scopes = std::mem::take(&mut *self.scopes.scopes.borrow_mut());
+        for scope in scopes.iter().filter_map(Weak::upgrade) {
+            // Taken out before it's dropped, as dropping the values can free other scopes
+            let data = std::mem::take(&mut scope.borrow_mut().data);
+            std::mem::drop(data);
+        }
+    }
+}
+
+impl Default for LisperEnv {
+    fn default() -> LisperEnv {
+        LisperEnv::from(HashMap::new())
+    }
+}
+
+impl LisperEnv {
+    // Creates an empty environment with no parent scope
+    pub fn new() -> LisperEnv {
+        LisperEnv::default()
+    }
+
+    // Creates a new, empty scope on top of this one
+    pub fn extend(&self) -> LisperEnv {
+        let scopes = self.scope.borrow().scopes.clone();
+        let scope = Rc::new(RefCell::new(LisperScope { data: HashMap::new(), parent: Some(self.capture()), scopes: scopes.clone() }));
+        scopes.add(&scope);
+        LisperEnv { scope, owner: self.owner.clone() }
+    }
+
+    // The env to keep inside a Lisper value or a child scope, which shares the scope
+    // without keeping the host application's env from being dropped
+    pub(crate) fn capture(&self) -> LisperEnv {
+        LisperEnv { scope: self.scope.clone(), owner: None }
+    }
+
+    // Looks up a name, starting in this scope and walking outward through the parents
+    pub fn get(&self, name: &str) -> Option<LisperExp> {
+        let mut env = self.clone();
+        loop {
+            let parent = {
+                let scope = env.scope.borrow();
+                if let Some(value) = scope.data.get(name) {
+                    return Some(value.clone());
+                }
+                scope.parent.clone()?
+            };
+            env = parent;
+        }
+    }
+
+    // Binds a name in this scope, shadowing any binding in the parent scopes
+    pub fn define(&self, name: String, value: LisperExp) {
+        self.scope.borrow_mut().data.insert(name, value);
+    }
+
+    // Binds a function implemented in Rust in this scope. It's called with the evaluated arguments,
+    // once they have been checked to be between min_args and max_args, or at least min_args
+    // if there is no max. The closure can capture state, such as a connection or a counter.
+    pub fn register_fn<F>(&self, name: &str, min_args: usize, max_args: Option<usize>, func: F)
+        where F: Fn(&[LisperExp], &mut LisperEnv) -> Result<LisperExp, LisperErr> + 'static
+    {
+        let native = LisperNative { name: name.to_string(), min_args, max_args, func: Rc::new(func) };
+        self.define(name.to_string(), LisperExp::Func(Rc::new(native)));
+    }
+
+    // Changes the value of the nearest existing binding of a name, walking outward
+    // through the parents. It's an error if the name isn't bound anywhere.
+    pub fn set(&self, name: &str, value: LisperExp) -> Result<(), LisperErr> {
+        let mut env = self.clone();
+        loop {
+            let parent = {
+                let mut scope = env.scope.borrow_mut();
+                if let Some(binding) = scope.data.get_mut(name) {
+                    *binding = value;
+                    return Ok(());
+                }
+                scope.parent.clone().ok_or_else(|| 
+                    LisperErr::UnboundSymbol(name.to_string())
+                )?
+            };
+            env = parent;
+        }
+    }
+}
+
+impl From<HashMap<String, LisperExp>> for LisperEnv {
+    fn from(data: HashMap<String, LisperExp>) -> LisperEnv {
+        let scopes = Rc::new(ScopeList::default());
+        let scope = Rc::new(RefCell::new(LisperScope { data, parent: None, scopes: scopes.clone() }));
+        scopes.add(&scope);
+        LisperEnv { scope, owner: Some(Rc::new(EnvOwner { scopes })) }
+    }
 }
 
 // Create a default environment containing fundamental functions
 pub fn create_default_env() -> LisperEnv {
-    let mut env_data: HashMap<String, LisperExp> = HashMap::new();
+    let env = LisperEnv::new();
 
     // Basic math functions
-    env_data.insert("+".to_string(), LisperExp::Func(add));
-    env_data.insert("-".to_string(), LisperExp::Func(sub));
-    env_data.insert("sub".to_string(), LisperExp::Func(sub));
-    env_data.insert("*".to_string(), LisperExp::Func(mul));
-    env_data.insert("mul".to_string(), LisperExp::Func(mul));
-    env_data.insert("/".to_string(), LisperExp::Func(div));
-    env_data.insert("div".to_string(), LisperExp::Func(div));
-    env_data.insert("%".to_string(), LisperExp::Func(modulus));
-    env_data.insert("mod".to_string(), LisperExp::Func(modulus));
+    env.register_fn("+", 0, None, add);
+    env.register_fn("-", 1, None, sub);
+    env.register_fn("sub", 1, None, sub);
+    env.register_fn("*", 0, None, mul);
+    env.register_fn("mul", 0, None, mul);
+    env.register_fn("/", 1, None, div);
+    env.register_fn("div", 1, None, div);
+    env.register_fn("%", 2, None, modulus);
+    env.register_fn("mod", 2, None, modulus);
+    env.register_fn("quot", 2, Some(2), quot);
+    env.register_fn("rem", 2, Some(2), rem);
+
+    // Fractions and conversions between exact and inexact numbers
+    env.register_fn("numerator", 1, Some(1), numerator);
+    env.register_fn("denominator", 1, Some(1), denominator);
+    env.register_fn("exact->inexact", 1, Some(1), exact_to_inexact);
+    env.register_fn("rationalize", 1, Some(2), rationalize);
 
     // Comparators
-    env_data.insert("<".to_string(), LisperExp::Func(less_than));
-    env_data.insert(">".to_string(), LisperExp::Func(more_than));
-    env_data.insert("=".to_string(), LisperExp::Func(equals));
-    env_data.insert("==".to_string(), LisperExp::Func(equals));
-    env_data.insert("<=".to_string(), LisperExp::Func(less_or_equal));
-    env_data.insert(">=".to_string(), LisperExp::Func(more_or_equal));
+    env.register_fn("<", 1, None, less_than);
+    env.register_fn(">", 1, None, more_than);
+    env.register_fn("=", 1, None, equals);
+    env.register_fn("==", 1, None, equals);
+    env.register_fn("<=", 1, None, less_or_equal);
+    env.register_fn(">=", 1, None, more_or_equal);
 
     // Trig functions
-    env_data.insert("sin".to_string(), LisperExp::Func(sin));
-    env_data.insert("cos".to_string(), LisperExp::Func(cos));
-    env_data.insert("tan".to_string(), LisperExp::Func(tan));
+    env.register_fn("sin", 1, Some(1), sin);
+    env.register_fn("cos", 1, Some(1), cos);
+    env.register_fn("tan", 1, Some(1), tan);
+
+    // Macro helpers
+    env.register_fn("gensym", 0, Some(1), gensym);
+
+    // List functions
+    env.register_fn("list", 0, None, list);
+    env.register_fn("cons", 2, Some(2), cons);
+    env.register_fn("car", 1, Some(1), car);
+    env.register_fn("first", 1, Some(1), car);
+    env.register_fn("cdr", 1, Some(1), cdr);
+    env.register_fn("rest", 1, Some(1), cdr);
+    env.register_fn("nth", 2, Some(2), nth);
+    env.register_fn("length", 1, Some(1), length);
+    env.register_fn("append", 0, None, append);
+    env.register_fn("reverse", 1, Some(1), reverse);
+    env.register_fn("last", 1, Some(1), last);
+    env.register_fn("take", 2, Some(2), take);
+    env.register_fn("drop", 2, Some(2), drop);
+    env.register_fn("range", 1, Some(3), range);
+    env.register_fn("empty?", 1, Some(1), is_empty);
+
+    // The empty list
+    env.define("nil".to_string(), LisperExp::List(vec![], None));
+
+    // Vector functions, vectors can also be given to the list functions
+    env.register_fn("vector", 0, None, vector);
+    env.register_fn("vec", 1, Some(1), vec);
+    env.register_fn("conj", 1, None, conj);
+    env.register_fn("subvec", 2, Some(3), subvec);
+
+    // Map functions, get, assoc and contains? also take vectors and indexes
+    env.register_fn("hash-map", 0, None, hash_map);
+    env.register_fn("get", 2, Some(3), get);
+    env.register_fn("assoc", 3, None, assoc);
+    env.register_fn("dissoc", 1, None, dissoc);
+    env.register_fn("keys", 1, Some(1), keys);
+    env.register_fn("vals", 1, Some(1), vals);
+    env.register_fn("contains?", 2, Some(2), contains);
+    env.register_fn("merge", 0, None, merge);
+    env.register_fn("update", 3, None, update);
+
+    // Higher order functions, which take builtin and user defined functions alike
+    env.register_fn("map", 2, None, map_lists);
+    env.register_fn("filter", 2, Some(2), filter);
+    env.register_fn("reduce", 2, Some(2), reduce);
+    env.register_fn("fold", 3, Some(3), fold);
+    env.register_fn("apply", 2, None, apply_list);
+    env.register_fn("for-each", 2, Some(2), for_each);
+    env.register_fn("sort-by", 2, Some(2), sort_by);
+    env.register_fn("any", 2, Some(2), any);
+    env.register_fn("every", 2, Some(2), every);
 
     // Trig constants
-    env_data.insert("pi".to_string(), LisperExp::Number(core::f64::consts::PI));
-    env_data.insert("two_pi".to_string(), LisperExp::Number(core::f64::consts::PI * 2.0));
-    env_data.insert("e".to_string(), LisperExp::Number(core::f64::consts::E));
+    env.define("pi".to_string(), LisperExp::Number(core::f64::consts::PI));
+    env.define("two_pi".to_string(), LisperExp::Number(core::f64::consts::PI * 2.0));
+    env.define("e".to_string(), LisperExp::Number(core::f64::consts::E));
 
-    LisperEnv {data: env_data}
+    env
 }
 
-fn add(args: &LisperExp) -> LisperExp {
-    let mut sum = 0.0;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    sum = *n;
-                } else {
-                    sum += n;
-                }
-            }
-        }
+// Gets the arguments of a builtin function that only takes numbers
+fn numbers(name: &str, args: &[LisperExp]) -> Result<Vec<Num>, LisperErr> {
+    args.iter()
+        .map(|arg| Num::from_exp(arg).ok_or_else(|| LisperErr::type_error(&format!("a number for {}", name), arg)))
+        .collect()
+}
+
+// Counts the symbols made by gensym, so that every one is unique
+static GENSYM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Creates a new symbol that won't clash with any other, for use in macro expansions.
+// The symbol starts with #:G, or #: and the given string prefix. The reader won't read
+// names starting with #:, so only gensym can make them.
+fn gensym(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let prefix = match args.first() {
+        Some(LisperExp::Str(prefix)) => prefix.to_string(),
+        Some(other) => return Err(LisperErr::type_error("a string prefix for gensym", other)),
+        None => "G".to_string()
+    };
+    let count = GENSYM_COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+
+    Ok(LisperExp::Symbol(format!("#:{}__{}", prefix, count)))
+}
+
+fn add(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let nums = numbers("+", args)?;
+    Ok(nums.iter().fold(Num::Int(0), |res, n| res.add(n)).into_exp())
+}
+
+// Subtracts the rest of the numbers from the first, or negates a single number
+fn sub(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let nums = numbers("-", args)?;
+    match nums.as_slice() {
+        [n] => Ok(n.neg().into_exp()),
+        [first, rest @ ..] => Ok(rest.iter().fold(first.clone(), |res, n| res.sub(n)).into_exp()),
+        [] => Ok(LisperExp::Int(0))
     }
-    LisperExp::Number(sum)
 }
 
-fn sub(args: &LisperExp) -> LisperExp {
-    let mut sum = 0.0;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    sum = *n;
-                } else {
-                    sum -= n;
-                }
-            }
-        }
+fn mul(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let nums = numbers("*", args)?;
+    Ok(nums.iter().fold(Num::Int(1), |res, n| res.mul(n)).into_exp())
+}
+
+// Divides the first number by the rest, or gives the reciprocal of a single number.
+// Exact numbers that don't divide exactly give an exact ratio, e.g. (/ 10 4) is 5/2.
+fn div(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let nums = numbers("/", args)?;
+    let (first, rest) = match nums.as_slice() {
+        [n] => (Num::Int(1), std::slice::from_ref(n)),
+        [first, rest @ ..] => (first.clone(), rest),
+        [] => (Num::Int(0), &[] as &[Num])
+    };
+    if rest.iter().any(Num::is_zero) {
+        return Err(LisperErr::DivisionByZero);
     }
-    LisperExp::Number(sum)
+    Ok(rest.iter().fold(first, |res, n| res.div(n)).into_exp())
 }
 
-fn mul(args: &LisperExp) -> LisperExp {
-    let mut sum = 0.0;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    sum = *n;
-                } else {
-                    sum *= n;
-                }
-            }
-        }
+// Folds the rest of the numbers into the first with an operation that can't divide by zero
+fn divide_by(name: &str, args: &[LisperExp], op: fn(&Num, &Num) -> Num) -> Result<LisperExp, LisperErr> {
+    let nums = numbers(name, args)?;
+    let (first, rest) = nums.split_first().ok_or_else(|| LisperErr::arity(name, 2, None, 0))?;
+    if rest.iter().any(Num::is_zero) {
+        return Err(LisperErr::DivisionByZero);
     }
-    LisperExp::Number(sum)
+    Ok(rest.iter().fold(first.clone(), |res, n| op(&res, n)).into_exp())
 }
 
-fn div(args: &LisperExp) -> LisperExp {
-    let mut sum = 0.0;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    sum = *n;
-                } else {
-                    sum /= n;
-                }
-            }
-        }
+// The remainder of dividing the first number by the rest, with the sign of the first
+fn modulus(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    divide_by("%", args, Num::rem)
+}
+
+// Divides two numbers, rounding towards zero, e.g. (quot 7 2) is 3
+fn quot(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    divide_by("quot", args, Num::quot)
+}
+
+// The remainder of dividing two numbers, so that (+ (* (quot a b) b) (rem a b)) is a
+fn rem(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    divide_by("rem", args, Num::rem)
+}
+
+// Gets the numerator of an exact number, which for an integer is itself
+fn numerator(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match Num::from_exp(&args[0]) {
+        Some(Num::Ratio(n)) => Ok(Num::Big(n.numer().clone()).into_exp()),
+        Some(Num::Int(_) | Num::Big(_)) => Ok(args[0].clone()),
+        _ => Err(LisperErr::type_error("an exact number for numerator", &args[0]))
     }
-    LisperExp::Number(sum)
 }
 
-fn modulus(args: &LisperExp) -> LisperExp {
-    let mut sum = 0.0;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    sum = *n;
-                } else {
-                    sum %= n;
-                }
-            }
-        }
+// Gets the denominator of an exact number in lowest terms, which for an integer is 1
+fn denominator(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match Num::from_exp(&args[0]) {
+        Some(Num::Ratio(n)) => Ok(Num::Big(n.denom().clone()).into_exp()),
+        Some(Num::Int(_) | Num::Big(_)) => Ok(LisperExp::Int(1)),
+        _ => Err(LisperErr::type_error("an exact number for denominator", &args[0]))
     }
-    LisperExp::Number(sum)
 }
 
-fn less_than(args: &LisperExp) -> LisperExp {
-    let mut prev = 0.0;
-    let mut res = false;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    prev = *n;
-                } else {
-                    res = prev < *n;
-                    prev = *n;
-                }
-            }
-        }
+// Converts a number to the nearest float, e.g. (exact->inexact 1/3)
+fn exact_to_inexact(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let nums = numbers("exact->inexact", args)?;
+    Ok(LisperExp::Number(nums[0].to_f64()))
+}
+
+// Converts a number to an exact one, a float becoming the fraction it prints as, e.g.
+// (rationalize 0.1) is 1/10. With a tolerance it gives the simplest fraction that close to
+// the number, e.g. (rationalize 0.333 1/100) is 1/3.
+fn rationalize(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let exact = numbers("rationalize", args)?.iter()
+        .map(|n| n.to_exact().ok_or_else(|| LisperErr::type_error("a finite number for rationalize", &n.clone().into_exp())))
+        .collect::<Result<Vec<Num>, LisperErr>>()?;
+    match exact.as_slice() {
+        [x, tolerance] => {
+            let tolerance = if tolerance.partial_cmp(&Num::Int(0)) == Some(Ordering::Less) { tolerance.neg() } else { tolerance.clone() };
+            Ok(simplest_between(&x.sub(&tolerance), &x.add(&tolerance)).into_exp())
+        },
+        [x] => Ok(x.clone().into_exp()),
+        _ => Err(LisperErr::arity("rationalize", 1, Some(2), args.len()))
     }
-    LisperExp::Bool(res)
 }
 
-fn more_than(args: &LisperExp) -> LisperExp {
-    let mut prev = 0.0;
-    let mut res = false;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    prev = *n;
-                } else {
-                    res = prev > *n;
-                    prev = *n;
-                }
-            }
-        }
+// Checks that every pair of neighbouring numbers is in the given order. Integers and
+// floats compare by value, and nothing is ordered with NaN.
+fn compare(name: &str, args: &[LisperExp], ordered: fn(Ordering) -> bool) -> Result<LisperExp, LisperErr> {
+    let nums = numbers(name, args)?;
+    Ok(LisperExp::Bool(nums.windows(2).all(|pair| pair[0].partial_cmp(&pair[1]).is_some_and(ordered))))
+}
+
+fn less_than(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    compare("<", args, Ordering::is_lt)
+}
+
+fn more_than(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    compare(">", args, Ordering::is_gt)
+}
+
+// Checks that every value is equal to the next, the way is_equal does
+fn equals(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::Bool(args.windows(2).all(|pair| is_equal(&pair[0], &pair[1]))))
+}
+
+// Compares two values the way = and case do. Numbers are equal when their values are,
+// e.g. (= 1 1.0). Anything else is equal when it's the same structurally, which includes
+// numbers inside lists, vectors and maps, so (= '(1) '(1.0)) is false, the same as 1 and 1.0
+// being different map keys.
+pub fn is_equal(a: &LisperExp, b: &LisperExp) -> bool {
+    match (Num::from_exp(a), Num::from_exp(b)) {
+        (Some(a), Some(b)) => a.partial_cmp(&b) == Some(Ordering::Equal),
+        _ => a == b
     }
-    LisperExp::Bool(res)
 }
 
-#[allow(clippy::float_cmp)]
-fn equals(args: &LisperExp) -> LisperExp {
-    let mut prev = 0.0;
-    let mut res = false;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    prev = *n;
-                } else {
-                    res = prev == *n;
-                    prev = *n;
+fn less_or_equal(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    compare("<=", args, Ordering::is_le)
+}
+
+fn more_or_equal(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    compare(">=", args, Ordering::is_ge)
+}
+
+// Gets an argument of a builtin function that has to be a list, or a vector, which list
+// functions take as well
+fn list_arg<'a>(name: &str, arg: &'a LisperExp) -> Result<&'a [LisperExp], LisperErr> {
+    match arg {
+        LisperExp::List(list, _) | LisperExp::Vector(list) => Ok(list),
+        _ => Err(LisperErr::type_error(&format!("a list or vector for {}", name), arg))
+    }
+}
+
+// Gets the items of an argument of a higher order function, which can be a list, a vector,
+// or a map whose entries are iterated as (key value) lists
+fn items_arg<'a>(name: &str, arg: &'a LisperExp) -> Result<Cow<'a, [LisperExp]>, LisperErr> {
+    match arg {
+        LisperExp::List(list, _) | LisperExp::Vector(list) => Ok(Cow::Borrowed(list)),
+        LisperExp::Map(map) => Ok(Cow::Owned(map_entries(map).into_iter()
+            .map(|(key, value)| LisperExp::List(vec![key.clone(), value.clone()], None))
+            .collect())),
+        _ => Err(LisperErr::type_error(&format!("a list, vector or map for {}", name), arg))
+    }
+}
+
+// Gets an argument of a builtin function that has to be a whole number, that isn't negative,
+// for indexing into a list
+fn index_arg(name: &str, arg: &LisperExp) -> Result<usize, LisperErr> {
+    let index = match arg {
+        // Integers that don't fit in a usize, such as ones past 2^32 on wasm32, aren't indexes
+        LisperExp::Int(n) => usize::try_from(*n).ok(),
+        // Float casts saturate, so huge floats can't wrap around to a valid index
+        LisperExp::Number(n) if *n >= 0.0 && n.fract() == 0.0 && n.is_finite() => Some(*n as usize),
+        _ => None
+    };
+    index.ok_or_else(|| LisperErr::type_error(&format!("a non-negative integer for {}", name), arg))
+}
+
+// Gets the first item and the rest of a list, which can't be empty
+fn split_list<'a>(name: &str, arg: &'a LisperExp) -> Result<(&'a LisperExp, &'a [LisperExp]), LisperErr> {
+    list_arg(name, arg)?.split_first()
+        .ok_or_else(|| LisperErr::type_error(&format!("a non-empty list for {}", name), arg))
+}
+
+// Creates a list of the arguments
+fn list(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::List(args.to_vec(), None))
+}
+
+// Creates a list with an item added to the front of another, e.g. (cons 1 '(2 3))
+fn cons(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let mut list: Vec<LisperExp> = vec![args[0].clone()];
+    list.extend(list_arg("cons", &args[1])?.iter().cloned());
+    Ok(LisperExp::List(list, None))
+}
+
+// Gets the first item of a list
+fn car(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(split_list("car", &args[0])?.0.clone())
+}
+
+// Gets a list of everything after the first item of a list
+fn cdr(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::List(split_list("cdr", &args[0])?.1.to_vec(), None))
+}
+
+// Gets an item of a list by its index, counting from 0
+fn nth(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let list = list_arg("nth", &args[0])?;
+    let index = index_arg("nth", &args[1])?;
+    list.get(index).cloned()
+        .ok_or_else(|| LisperErr::IndexOutOfRange(format!("index {} is out of range for a list of length {}", index, list.len())))
+}
+
+// Counts the items of a list, the entries of a map, or the characters of a string
+fn length(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match &args[0] {
+        LisperExp::Str(s) => Ok(LisperExp::Int(s.chars().count() as i64)),
+        LisperExp::Map(map) => Ok(LisperExp::Int(map.len() as i64)),
+        arg => Ok(LisperExp::Int(list_arg("length", arg)?.len() as i64))
+    }
+}
+
+// Joins lists together into one
+fn append(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let mut joined: Vec<LisperExp> = vec![];
+    for arg in args {
+        joined.extend(list_arg("append", arg)?.iter().cloned());
+    }
+    Ok(LisperExp::List(joined, None))
+}
+
+// Creates a list with the items of another in the opposite order
+fn reverse(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::List(list_arg("reverse", &args[0])?.iter().rev().cloned().collect(), None))
+}
+
+// Gets the last item of a list
+fn last(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    list_arg("last", &args[0])?.last().cloned()
+        .ok_or_else(|| LisperErr::type_error("a non-empty list for last", &args[0]))
+}
+
+// Gets the first n items of a list, or all of them if there are fewer, e.g. (take 2 '(1 2 3))
+fn take(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let n = index_arg("take", &args[0])?;
+    let list = list_arg("take", &args[1])?;
+    Ok(LisperExp::List(list[..n.min(list.len())].to_vec(), None))
+}
+
+// Gets the items of a list after the first n, e.g. (drop 2 '(1 2 3))
+fn drop(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let n = index_arg("drop", &args[0])?;
+    let list = list_arg("drop", &args[1])?;
+    Ok(LisperExp::List(list[n.min(list.len())..].to_vec(), None))
+}
+
+// The most items range will create, so a typo can't use up all of the memory
+const RANGE_LIMIT: f64 = 10_000_000.0;
+
+// Creates a list of numbers counting up from start, or 0, to before end, e.g. (range 5),
+// (range 1 5) or (range 10 0 -2)
+fn range(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let (start, end, step) = match numbers("range", args)?.as_slice() {
+        [end] => (Num::Int(0), end.clone(), Num::Int(1)),
+        [start, end] => (start.clone(), end.clone(), Num::Int(1)),
+        [start, end, step] => (start.clone(), end.clone(), step.clone()),
+        _ => return Err(LisperErr::arity("range", 1, Some(3), args.len()))
+    };
+    if step.is_zero() {
+        return Err(LisperErr::type_error("a step other than 0 for range", &args[2]));
+    }
+    // Exact bounds count exactly, as floats can't tell big integers that are close apart
+    let count = if start.is_float() || end.is_float() || step.is_float() {
+        ((end.to_f64() - start.to_f64()) / step.to_f64()).ceil().max(0.0)
+    } else {
+        end.sub(&start).div(&step).neg().floor().neg().to_f64().max(0.0)
+    };
+    if count.is_nan() || count > RANGE_LIMIT {
+        return Err(LisperErr::ResourceLimit(format!("range can create at most {} items", RANGE_LIMIT)));
+    }
+    // Integers stay exact, and any float makes every item a float
+    Ok(LisperExp::List((0..count as i64).map(|i| start.add(&Num::Int(i).mul(&step)).into_exp()).collect(), None))
+}
+
+// Checks whether a list or map has no items
+fn is_empty(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match &args[0] {
+        LisperExp::Map(map) => Ok(LisperExp::Bool(map.is_empty())),
+        arg => Ok(LisperExp::Bool(list_arg("empty?", arg)?.is_empty()))
+    }
+}
+
+// Creates a vector of the arguments
+fn vector(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::Vector(args.to_vec()))
+}
+
+// Creates a vector of the items of a list, vector or map
+fn vec(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    Ok(LisperExp::Vector(items_arg("vec", &args[0])?.into_owned()))
+}
+
+// Adds items to a collection where it's cheapest: the end of a vector, the front of a list,
+// or as (key value) entries of a map, e.g. (conj [1 2] 3)
+#[allow(clippy::mutable_key_type)]
+fn conj(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match &args[0] {
+        LisperExp::Vector(items) => Ok(LisperExp::Vector(items.iter().chain(&args[1..]).cloned().collect())),
+        LisperExp::List(items, _) => Ok(LisperExp::List(args[1..].iter().rev().chain(items).cloned().collect(), None)),
+        LisperExp::Map(map) => {
+            let mut map = map.clone();
+            for entry in &args[1..] {
+                match entry {
+                    LisperExp::List(pair, _) | LisperExp::Vector(pair) if pair.len() == 2 => {
+                        map.insert(pair[0].clone(), pair[1].clone());
+                    },
+                    _ => return Err(LisperErr::type_error("a [key value] entry for conj", entry))
                 }
             }
+            Ok(LisperExp::Map(map))
+        },
+        arg => Err(LisperErr::type_error("a list, vector or map for conj", arg))
+    }
+}
+
+// Gets the items of a vector from the start index to before the end one, or the end of the
+// vector, e.g. (subvec [1 2 3 4] 1 3)
+fn subvec(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = match &args[0] {
+        LisperExp::Vector(items) => items,
+        arg => return Err(LisperErr::type_error("a vector for subvec", arg))
+    };
+    let start = index_arg("subvec", &args[1])?;
+    let end = args.get(2).map_or(Ok(items.len()), |end| index_arg("subvec", end))?;
+    if start > end || end > items.len() {
+        return Err(LisperErr::IndexOutOfRange(format!("subvec from {} to {} is out of range for a vector of length {}", start, end, items.len())));
+    }
+    Ok(LisperExp::Vector(items[start..end].to_vec()))
+}
+
+// Gets an argument of a builtin function that has to be a map
+fn map_arg<'a>(name: &str, arg: &'a LisperExp) -> Result<&'a HashMap<LisperExp, LisperExp>, LisperErr> {
+    match arg {
+        LisperExp::Map(map) => Ok(map),
+        _ => Err(LisperErr::type_error(&format!("a map for {}", name), arg))
+    }
+}
+
+// Adds the key and value pairs in the arguments from first on to a map, replacing the values
+// of keys it already has
+#[allow(clippy::mutable_key_type)]
+fn insert_pairs(name: &str, mut map: HashMap<LisperExp, LisperExp>, args: &[LisperExp], first: usize) -> Result<LisperExp, LisperErr> {
+    check_pairs(name, args, first)?;
+    for pair in args[first..].chunks(2) {
+        map.insert(pair[0].clone(), pair[1].clone());
+    }
+    Ok(LisperExp::Map(map))
+}
+
+// Checks that every key in the arguments from first on has a value after it
+fn check_pairs(name: &str, args: &[LisperExp], first: usize) -> Result<(), LisperErr> {
+    if args[first..].len().is_multiple_of(2) {
+        Ok(())
+    } else {
+        let expected = if first.is_multiple_of(2) { "an even number of" } else { "an odd number of" };
+        Err(LisperErr::Arity { name: name.to_string(), expected: expected.to_string(), got: args.len() })
+    }
+}
+
+// Creates a map from key and value pairs, e.g. (hash-map :a 1 :b 2)
+fn hash_map(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    insert_pairs("hash-map", HashMap::new(), args, 0)
+}
+
+// Gets the value of a key in a map, or an index in a vector, or the default, or nil, if it
+// isn't there
+fn get(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let found = match &args[0] {
+        LisperExp::Vector(items) => index_arg("get", &args[1]).ok().and_then(|index| items.get(index)),
+        arg => map_arg("get", arg)?.get(&args[1])
+    };
+    Ok(found.or(args.get(2)).cloned().unwrap_or(LisperExp::List(vec![], None)))
+}
+
+// Creates a map with keys set to new values, e.g. (assoc person :age 30), or a vector with
+// the items at indexes replaced. The index after the last item adds one to the end.
+fn assoc(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let LisperExp::Vector(items) = &args[0] else {
+        return insert_pairs("assoc", map_arg("assoc", &args[0])?.clone(), args, 1);
+    };
+    check_pairs("assoc", args, 1)?;
+    let mut items = items.clone();
+    for pair in args[1..].chunks(2) {
+        match index_arg("assoc", &pair[0])? {
+            index if index < items.len() => items[index] = pair[1].clone(),
+            index if index == items.len() => items.push(pair[1].clone()),
+            index => return Err(LisperErr::IndexOutOfRange(format!("index {} is out of range for a vector of length {}", index, items.len())))
         }
     }
-    LisperExp::Bool(res)
+    Ok(LisperExp::Vector(items))
 }
 
-fn less_or_equal(args: &LisperExp) -> LisperExp {
-    let mut prev = 0.0;
-    let mut res = false;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    prev = *n;
-                } else {
-                    res = prev <= *n;
-                    println!("{} <= {} = {}", prev.to_string(), n.to_string(), res.to_string());
-                    prev = *n;
-                }
-            }
+// Creates a map without the given keys
+#[allow(clippy::mutable_key_type)]
+fn dissoc(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let mut map = map_arg("dissoc", &args[0])?.clone();
+    for key in &args[1..] {
+        map.remove(key);
+    }
+    Ok(LisperExp::Map(map))
+}
+
+// Lists the keys of a map, in the order it prints them
+fn keys(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let entries = map_entries(map_arg("keys", &args[0])?);
+    Ok(LisperExp::List(entries.into_iter().map(|(key, _)| key.clone()).collect(), None))
+}
+
+// Lists the values of a map, in the same order as its keys
+fn vals(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let entries = map_entries(map_arg("vals", &args[0])?);
+    Ok(LisperExp::List(entries.into_iter().map(|(_, value)| value.clone()).collect(), None))
+}
+
+// Checks whether a map has a key, or a vector has an index
+fn contains(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    match &args[0] {
+        LisperExp::Vector(items) => Ok(LisperExp::Bool(index_arg("contains?", &args[1]).is_ok_and(|index| index < items.len()))),
+        arg => Ok(LisperExp::Bool(map_arg("contains?", arg)?.contains_key(&args[1])))
+    }
+}
+
+// Combines maps into one, where the later maps win for keys they share
+#[allow(clippy::mutable_key_type)]
+fn merge(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let mut merged: HashMap<LisperExp, LisperExp> = HashMap::new();
+    for arg in args {
+        merged.extend(map_arg("merge", arg)?.iter().map(|(key, value)| (key.clone(), value.clone())));
+    }
+    Ok(LisperExp::Map(merged))
+}
+
+// Creates a map with the value of a key replaced by calling a function on it, along with
+// any extra arguments, e.g. (update counts :total + 1)
+#[allow(clippy::mutable_key_type)]
+fn update(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let mut map = map_arg("update", &args[0])?.clone();
+    let mut call_args: Vec<LisperExp> = vec![map.get(&args[1]).cloned().unwrap_or(LisperExp::List(vec![], None))];
+    call_args.extend(args[3..].iter().cloned());
+    let value = apply(&args[2], call_args, env)?;
+    map.insert(args[1].clone(), value);
+    Ok(LisperExp::Map(map))
+}
+
+// Calls a function on the items of one or more lists, e.g. (map + '(1 2) '(10 20)),
+// stopping at the end of the shortest list
+fn map_lists(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let lists = args[1..].iter().map(|arg| items_arg("map", arg)).collect::<Result<Vec<_>, LisperErr>>()?;
+    let length = lists.iter().map(|list| list.len()).min().unwrap_or(0);
+    let mut mapped: Vec<LisperExp> = vec![];
+    for i in 0..length {
+        mapped.push(apply(&args[0], lists.iter().map(|list| list[i].clone()).collect(), env)?);
+    }
+    Ok(LisperExp::List(mapped, None))
+}
+
+// Keeps the items of a list that a predicate holds for
+fn filter(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let mut kept: Vec<LisperExp> = vec![];
+    for item in items_arg("filter", &args[1])?.iter() {
+        if is_true("filter", &apply(&args[0], vec![item.clone()], env)?)? {
+            kept.push(item.clone());
         }
     }
-    LisperExp::Bool(res)
+    Ok(LisperExp::List(kept, None))
 }
 
-fn more_or_equal(args: &LisperExp) -> LisperExp {
-    let mut prev = 0.0;
-    let mut res = false;
-    if let LisperExp::List(list) = args {
-        for (i, arg) in list.iter().enumerate() {
-            if let LisperExp::Number(n) = arg {
-                if i == 0 {
-                    prev = *n;
-                } else {
-                    res = prev >= *n;
-                    prev = *n;
+// Combines the items of a list from the left with a function, starting from the first item
+fn reduce(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let items = items_arg("reduce", &args[1])?;
+    let (first, rest) = items.split_first()
+        .ok_or_else(|| LisperErr::type_error("a list with at least 1 item for reduce", &args[1]))?;
+    rest.iter().try_fold(first.clone(), |acc, item| apply(&args[0], vec![acc, item.clone()], env))
+}
+
+// Combines the items of a list from the left with a function, starting from an initial value
+fn fold(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    items_arg("fold", &args[2])?.iter()
+        .try_fold(args[1].clone(), |acc, item| apply(&args[0], vec![acc, item.clone()], env))
+}
+
+// Calls a function with the items of a list as its arguments, after any given before the list,
+// e.g. (apply + 1 '(2 3))
+fn apply_list(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let (last, init) = args[1..].split_last().ok_or_else(|| LisperErr::arity("apply", 2, None, args.len()))?;
+    let mut call_args: Vec<LisperExp> = init.to_vec();
+    call_args.extend(list_arg("apply", last)?.iter().cloned());
+    apply(&args[0], call_args, env)
+}
+
+// Calls a function on each item of a list for its side effects
+fn for_each(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    for item in items_arg("for-each", &args[1])?.iter() {
+        apply(&args[0], vec![item.clone()], env)?;
+    }
+    Ok(LisperExp::Bool(true))
+}
+
+// Orders numbers, strings, and vectors of them item by item, e.g. [1 "b"] comes before [2 "a"].
+// Values of different types have no order.
+fn order(a: &LisperExp, b: &LisperExp) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (Num::from_exp(a), Num::from_exp(b)) {
+        return Some(a.partial_cmp(&b).unwrap_or(Ordering::Equal));
+    }
+    match (a, b) {
+        (LisperExp::Str(a), LisperExp::Str(b)) => Some(a.cmp(b)),
+        (LisperExp::Vector(a), LisperExp::Vector(b)) => {
+            for (a, b) in a.iter().zip(b) {
+                match order(a, b)? {
+                    Ordering::Equal => {},
+                    unequal => return Some(unequal)
                 }
             }
-        }
+            Some(a.len().cmp(&b.len()))
+        },
+        _ => None
     }
-    LisperExp::Bool(res)
 }
 
-fn sin(args: &LisperExp) -> LisperExp {
-    let mut res = 0.0;
-    if let LisperExp::List(list) = args {
-        if let LisperExp::Number(n) = list[0] {
-            res = n.sin();
+// Sorts a list by the number, string or vector a key function gives for each item. Items with
+// equal keys keep their order.
+fn sort_by(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    let list = items_arg("sort-by", &args[1])?;
+    let mut keyed: Vec<(LisperExp, LisperExp)> = vec![];
+    for item in list.iter() {
+        let key = apply(&args[0], vec![item.clone()], env)?;
+        // Keys have to be ordered the same way as the first one
+        if order(&key, keyed.first().map_or(&key, |(first, _)| first)).is_none() {
+            return Err(LisperErr::type_error("sort-by keys to be all numbers, all strings or all vectors of them", &key));
         }
+        keyed.push((key, item.clone()));
     }
-    LisperExp::Number(res)
+    keyed.sort_by(|(a, _), (b, _)| order(a, b).unwrap_or(Ordering::Equal));
+    Ok(LisperExp::List(keyed.into_iter().map(|(_, item)| item).collect(), None))
 }
 
-fn cos(args: &LisperExp) -> LisperExp {
-    let mut res = 0.0;
-    if let LisperExp::List(list) = args {
-        if let LisperExp::Number(n) = list[0] {
-            res = n.cos();
+// Checks whether a predicate holds for any item of a list, stopping at the first that it does
+fn any(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    for item in items_arg("any", &args[1])?.iter() {
+        if is_true("any", &apply(&args[0], vec![item.clone()], env)?)? {
+            return Ok(LisperExp::Bool(true));
         }
     }
-    LisperExp::Number(res)
+    Ok(LisperExp::Bool(false))
 }
 
-fn tan(args: &LisperExp) -> LisperExp {
-    let mut res = 0.0;
-    if let LisperExp::List(list) = args {
-        if let LisperExp::Number(n) = list[0] {
-            res = n.tan();
+// Checks whether a predicate holds for every item of a list, stopping at the first that it doesn't
+fn every(args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    for item in items_arg("every", &args[1])?.iter() {
+        if !is_true("every", &apply(&args[0], vec![item.clone()], env)?)? {
+            return Ok(LisperExp::Bool(false));
         }
     }
-    LisperExp::Number(res)
+    Ok(LisperExp::Bool(true))
+}
+
+// Applies a function of one number
+fn unary(name: &str, args: &[LisperExp], f: fn(f64) -> f64) -> Result<LisperExp, LisperErr> {
+    match numbers(name, args)?.as_slice() {
+        [n] => Ok(LisperExp::Number(f(n.to_f64()))),
+        _ => Err(LisperErr::arity(name, 1, Some(1), args.len()))
+    }
+}
+
+fn sin(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    unary("sin", args, f64::sin)
+}
+
+fn cos(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    unary("cos", args, f64::cos)
+}
+
+fn tan(args: &[LisperExp], _env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+    unary("tan", args, f64::tan)
 }
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
+    #[test]
+    fn env_scope_chain() {
+        use super::*;
+
+        let global:LisperEnv = LisperEnv::new();
+        global.define("a".to_string(), LisperExp::Number(1.0));
+        global.define("b".to_string(), LisperExp::Number(2.0));
+
+        // Lookups walk outward, and definitions shadow without touching the parent
+        let local:LisperEnv = global.extend();
+        local.define("b".to_string(), LisperExp::Number(3.0));
+        let inner:LisperEnv = local.extend();
+
+        for (name, expected) in [("a", 1.0), ("b", 3.0)].iter() {
+            match inner.get(name) {
+                Some(LisperExp::Number(n)) => assert_eq!(n, *expected),
+                _ => assert!(false)
+            }
+        }
+        match global.get("b") {
+            Some(LisperExp::Number(n)) => assert_eq!(n, 2.0),
+            _ => assert!(false)
+        }
+        assert!(inner.get("c").is_none());
+
+        // Clones share the same scope
+        global.clone().define("c".to_string(), LisperExp::Bool(true));
+        assert!(inner.get("c").is_some());
+    }
+
+    #[test]
+    fn env_set() {
+        use super::*;
+
+        let global:LisperEnv = LisperEnv::new();
+        global.define("a".to_string(), LisperExp::Number(1.0));
+        let local:LisperEnv = global.extend();
+
+        // set changes the nearest binding, wherever it is
+        assert!(local.set("a", LisperExp::Number(2.0)).is_ok());
+        match global.get("a") {
+            Some(LisperExp::Number(n)) => assert_eq!(n, 2.0),
+            _ => assert!(false)
+        }
+
+        // and doesn't create new ones
+        assert!(local.set("b", LisperExp::Number(2.0)).is_err());
+        assert!(global.get("b").is_none());
+    }
+
+    #[test]
+    fn env_register_fn() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        use std::cell::Cell;
+        use crate::core::{ apply, eval_program, parse_program, tokenize };
+
+        let env:LisperEnv = create_default_env();
+        let run = |source: &str| -> Result<LisperExp, LisperErr> {
+            let results = eval_program(parse_program(&tokenize(source.to_string()))?, &mut env.clone())?;
+            Ok(results.last().cloned().unwrap_or(LisperExp::Bool(false)))
+        };
+
+        // Native functions can capture state from the host
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        env.register_fn("count-call", 0, Some(0), move |_, _| {
+            counter.set(counter.get() + 1);
+            Ok(LisperExp::Int(counter.get()))
+        });
+        // and call back into Lisper values they are given
+        env.register_fn("call-twice", 2, Some(2), |args, env| {
+            let once = apply(&args[0], vec![args[1].clone()], env)?;
+            apply(&args[0], vec![once], env)
+        });
+
+        run("(count-call) (count-call)")?;
+        assert_eq!(calls.get(), 2);
+        assert!(run("(call-twice (lambda (x) (* x 3)) 2)")? == LisperExp::Int(18));
+        assert!(run("(call-twice - 2)")? == LisperExp::Int(2));
+
+        // Arguments are checked against the arity it was registered with
+        match run("(count-call 1)") {
+            Err(e) => assert!(e.to_string().starts_with("Arity error, count-call expects 0 arguments, got 1.")),
+            Ok(_) => assert!(false)
+        }
+        // and errors from callbacks are returned
+        assert!(run("(call-twice (lambda (x) (error \"failed\")) 2)").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn env_freed_on_drop() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        use crate::core::{ eval_program, parse_program, tokenize };
+
+        // Functions that capture the env are stored in it, which mustn't keep it alive
+        let mut env:LisperEnv = create_default_env();
+        let token = Rc::new(());
+        let held = token.clone();
+        env.register_fn("token", 0, Some(0), move |_, _| Ok(LisperExp::Int(Rc::strong_count(&held) as i64)));
+        let source = "(fn twice x (* x 2)) (fn make-adder n (lambda (x) (+ x n))) (def add-one (make-adder 1))
+                      (defmacro unless c body `(if ,c false ,body)) (let ((local (lambda () 1))) (local))
+                      (letrec ((even? (lambda (n) (if (< n 1) true (odd? (- n 1))))) (odd? (lambda (n) (even? (- n 1))))) (even? 4))
+                      (add-one (twice 1))";
+        let results = eval_program(parse_program(&tokenize(source.to_string()))?, &mut env)?;
+        assert!(results.last() == Some(&LisperExp::Int(3)));
+        std::mem::drop(results);
+
+        // and neither are the scopes of lets and calls, when functions defined in them capture them.
+        // Each of these scopes holds on to the token function, which holds on to the token.
+        let source = "(let ((t token)) (fn inner () (t)) (inner))
+                      (fn outer f (fn helper () (f)) (helper)) (outer token)
+                      (letrec ((t token) (again (lambda () (t)))) (again))
+                      (def counter (let ((n 0) (t token)) (lambda () (set! n (+ n 1)) (t))))
+                      (counter)";
+        let results = eval_program(parse_program(&tokenize(source.to_string()))?, &mut env)?;
+        assert!(results.last() == Some(&LisperExp::Int(2)));
+        std::mem::drop(results);
+
+        let scope = Rc::downgrade(&env.scope);
+        let local = env.extend();
+        std::mem::drop(env);
+        // Envs extended from it keep it alive
+        assert!(local.get("counter").is_some());
+        std::mem::drop(local);
+        assert!(scope.upgrade().is_none());
+        assert_eq!(Rc::strong_count(&token), 1);
+
+        // including ones extended from a temporary env
+        let local = create_default_env().extend();
+        let results = eval_program(parse_program(&tokenize("(fn f x (+ x 1)) (f 1)".to_string()))?, &mut local.clone())?;
+        assert!(results.last() == Some(&LisperExp::Int(2)));
+
+        Ok(())
+    }
+
     #[test]
     fn create_default_env_add() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
@@ -253,7 +999,7 @@ mod tests {
 
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get("+").ok_or_else(|| 
+        let func = env.get("+").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -265,7 +1011,7 @@ mod tests {
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
                 let arg1:LisperExp = LisperExp::Number(arg1_f64);
         
-                if let LisperExp::Number(res) = f(&LisperExp::List(vec![arg0, arg1])) {
+                if let LisperExp::Number(res) = f.call(&[arg0, arg1], &mut env.clone())? {
                     assert_eq!(res, arg0_f64 + arg1_f64);
                 } else {
                     assert!(false);
@@ -284,7 +1030,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get("-").ok_or_else(|| 
+        let func = env.get("-").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -296,7 +1042,7 @@ mod tests {
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
                 let arg1:LisperExp = LisperExp::Number(arg1_f64);
         
-                if let LisperExp::Number(res) = f(&LisperExp::List(vec![arg0, arg1])) {
+                if let LisperExp::Number(res) = f.call(&[arg0, arg1], &mut env.clone())? {
                     assert_eq!(res, arg0_f64 - arg1_f64);
                 } else {
                     assert!(false);
@@ -315,7 +1061,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get("*").ok_or_else(|| 
+        let func = env.get("*").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -327,7 +1073,7 @@ mod tests {
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
                 let arg1:LisperExp = LisperExp::Number(arg1_f64);
         
-                if let LisperExp::Number(res) = f(&LisperExp::List(vec![arg0, arg1])) {
+                if let LisperExp::Number(res) = f.call(&[arg0, arg1], &mut env.clone())? {
                     assert_eq!(res, arg0_f64 * arg1_f64);
                 } else {
                     assert!(false);
@@ -346,7 +1092,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get("/").ok_or_else(|| 
+        let func = env.get("/").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -358,7 +1104,7 @@ mod tests {
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
                 let arg1:LisperExp = LisperExp::Number(arg1_f64);
         
-                if let LisperExp::Number(res) = f(&LisperExp::List(vec![arg0, arg1])) {
+                if let LisperExp::Number(res) = f.call(&[arg0, arg1], &mut env.clone())? {
                     assert_eq!(res, arg0_f64 / arg1_f64);
                 } else {
                     assert!(false);
@@ -377,7 +1123,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get("%").ok_or_else(|| 
+        let func = env.get("%").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -389,7 +1135,7 @@ mod tests {
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
                 let arg1:LisperExp = LisperExp::Number(arg1_f64);
         
-                if let LisperExp::Number(res) = f(&LisperExp::List(vec![arg0, arg1])) {
+                if let LisperExp::Number(res) = f.call(&[arg0, arg1], &mut env.clone())? {
                     assert_eq!(res, arg0_f64 % arg1_f64);
                 } else {
                     assert!(false);
@@ -408,7 +1154,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get("<").ok_or_else(|| 
+        let func = env.get("<").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -420,7 +1166,7 @@ mod tests {
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
                 let arg1:LisperExp = LisperExp::Number(arg1_f64);
         
-                if let LisperExp::Bool(res) = f(&LisperExp::List(vec![arg0, arg1])) {
+                if let LisperExp::Bool(res) = f.call(&[arg0, arg1], &mut env.clone())? {
                     assert_eq!(res, arg0_f64 < arg1_f64);
                 } else {
                     assert!(false);
@@ -439,7 +1185,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get(">").ok_or_else(|| 
+        let func = env.get(">").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -451,7 +1197,7 @@ mod tests {
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
                 let arg1:LisperExp = LisperExp::Number(arg1_f64);
         
-                if let LisperExp::Bool(res) = f(&LisperExp::List(vec![arg0, arg1])) {
+                if let LisperExp::Bool(res) = f.call(&[arg0, arg1], &mut env.clone())? {
                     assert_eq!(res, arg0_f64 > arg1_f64);
                 } else {
                     assert!(false);
@@ -470,7 +1216,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get("=").ok_or_else(|| 
+        let func = env.get("=").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -482,7 +1228,7 @@ mod tests {
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
                 let arg1:LisperExp = LisperExp::Number(arg1_f64);
         
-                if let LisperExp::Bool(res) = f(&LisperExp::List(vec![arg0, arg1])) {
+                if let LisperExp::Bool(res) = f.call(&[arg0, arg1], &mut env.clone())? {
                     assert_eq!(res, arg0_f64 == arg1_f64);
                 } else {
                     assert!(false);
@@ -494,6 +1240,45 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn create_default_env_equals_str() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+        use crate::core::LisperErr;
+        
+        let env:LisperEnv = create_default_env();
+
+        let func = env.get("=").ok_or_else(|| 
+                LisperErr::Reason("Error, function not found.".to_string())
+            )?;
+
+        match func {
+            LisperExp::Func(f) => {
+                let same = vec![LisperExp::Str("a b".to_string()), LisperExp::Str("a b".to_string())];
+                let different = vec![LisperExp::Str("a".to_string()), LisperExp::Str("b".to_string())];
+                let mixed = vec![LisperExp::Str("1".to_string()), LisperExp::Number(1.0)];
+
+                if let LisperExp::Bool(res) = f.call(&same, &mut env.clone())? {
+                    assert!(res);
+                } else {
+                    assert!(false);
+                }
+                if let LisperExp::Bool(res) = f.call(&different, &mut env.clone())? {
+                    assert!(!res);
+                } else {
+                    assert!(false);
+                }
+                if let LisperExp::Bool(res) = f.call(&mixed, &mut env.clone())? {
+                    assert!(!res);
+                } else {
+                    assert!(false);
+                }
+            },
+            _ => assert!(false)
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn create_default_env_less_or_equal() -> Result<(),  Box<dyn std::error::Error>> {
         use super::*;
@@ -501,7 +1286,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get("<=").ok_or_else(|| 
+        let func = env.get("<=").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -513,7 +1298,7 @@ mod tests {
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
                 let arg1:LisperExp = LisperExp::Number(arg1_f64);
         
-                if let LisperExp::Bool(res) = f(&LisperExp::List(vec![arg0, arg1])) {
+                if let LisperExp::Bool(res) = f.call(&[arg0, arg1], &mut env.clone())? {
                     assert_eq!(res, arg0_f64 <= arg1_f64);
                 } else {
                     assert!(false);
@@ -532,7 +1317,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get(">=").ok_or_else(|| 
+        let func = env.get(">=").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -544,7 +1329,7 @@ mod tests {
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
                 let arg1:LisperExp = LisperExp::Number(arg1_f64);
         
-                if let LisperExp::Bool(res) = f(&LisperExp::List(vec![arg0, arg1])) {
+                if let LisperExp::Bool(res) = f.call(&[arg0, arg1], &mut env.clone())? {
                     assert_eq!(res, arg0_f64 >= arg1_f64);
                 } else {
                     assert!(false);
@@ -563,7 +1348,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get("sin").ok_or_else(|| 
+        let func = env.get("sin").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -573,7 +1358,7 @@ mod tests {
 
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
         
-                if let LisperExp::Number(res) = f(&LisperExp::List(vec![arg0])) {
+                if let LisperExp::Number(res) = f.call(&[arg0], &mut env.clone())? {
                     assert_eq!(res, arg0_f64.sin());
                 } else {
                     assert!(false);
@@ -592,7 +1377,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get("cos").ok_or_else(|| 
+        let func = env.get("cos").ok_or_else(|| 
             LisperErr::Reason("Error, function not found.".to_string())
         )?;
 
@@ -602,7 +1387,7 @@ mod tests {
 
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
         
-                if let LisperExp::Number(res) = f(&LisperExp::List(vec![arg0])) {
+                if let LisperExp::Number(res) = f.call(&[arg0], &mut env.clone())? {
                     assert_eq!(res, arg0_f64.cos());
                 } else {
                     assert!(false);
@@ -621,7 +1406,7 @@ mod tests {
         
         let env:LisperEnv = create_default_env();
 
-        let func = env.data.get("tan").ok_or_else(|| 
+        let func = env.get("tan").ok_or_else(|| 
                 LisperErr::Reason("Error, function not found.".to_string())
             )?;
 
@@ -631,7 +1416,7 @@ mod tests {
 
                 let arg0:LisperExp = LisperExp::Number(arg0_f64);
         
-                if let LisperExp::Number(res) = f(&LisperExp::List(vec![arg0])) {
+                if let LisperExp::Number(res) = f.call(&[arg0], &mut env.clone())? {
                     assert_eq!(res, arg0_f64.tan());
                 } else {
                     assert!(false);
@@ -642,4 +1427,54 @@ mod tests {
 
         Ok(())
     }
-}
\ No newline at end of file
+    #[test]
+    fn create_default_env_errors() {
+        use super::*;
+
+        let env:LisperEnv = create_default_env();
+
+        let call = |name: &str, args: Vec<LisperExp>| match env.get(name) {
+            Some(LisperExp::Func(f)) => f.call(&args, &mut env.clone()),
+            _ => Err(LisperErr::Reason("Error, function not found.".to_string()))
+        };
+
+        match call("+", vec![LisperExp::Number(1.0), LisperExp::Bool(true)]) {
+            Err(e) => assert_eq!(e.to_string(), "Type error, expected a number for +, found true."),
+            Ok(_) => assert!(false)
+        }
+        match call("sin", vec![]) {
+            Err(e) => assert_eq!(e.to_string(), "Arity error, sin expects 1 arguments, got 0."),
+            Ok(_) => assert!(false)
+        }
+        assert!(matches!(call("/", vec![LisperExp::Number(1.0), LisperExp::Number(0.0)]), Err(LisperErr::DivisionByZero)));
+        assert!(matches!(call("%", vec![LisperExp::Number(1.0), LisperExp::Number(0.0)]), Err(LisperErr::DivisionByZero)));
+        assert!(call("%", vec![LisperExp::Number(1.0)]).is_err());
+        assert!(call("<", vec![LisperExp::Number(1.0), LisperExp::Str("2".to_string())]).is_err());
+        assert!(call("-", vec![]).is_err());
+        assert!(call("gensym", vec![LisperExp::Number(1.0)]).is_err());
+    }
+
+    #[test]
+    fn create_default_env_chains() -> Result<(),  Box<dyn std::error::Error>> {
+        use super::*;
+
+        let env:LisperEnv = create_default_env();
+
+        let call = |name: &str, args: &[f64]| match env.get(name) {
+            Some(LisperExp::Func(f)) => f.call(&args.iter().map(|n| LisperExp::Number(*n)).collect::<Vec<LisperExp>>(), &mut env.clone()),
+            _ => Err(LisperErr::Reason("Error, function not found.".to_string()))
+        };
+
+        // Comparisons hold for every neighbouring pair, not just the last one
+        assert!(call("<", &[1.0, 3.0, 2.0])? == LisperExp::Bool(false));
+        assert!(call("<", &[1.0, 2.0, 3.0])? == LisperExp::Bool(true));
+        assert!(call("=", &[2.0, 2.0, 3.0])? == LisperExp::Bool(false));
+        // and a single argument negates or inverts it
+        assert!(call("-", &[5.0])? == LisperExp::Number(-5.0));
+        assert!(call("/", &[4.0])? == LisperExp::Number(0.25));
+        assert!(call("+", &[])? == LisperExp::Int(0));
+        assert!(call("*", &[])? == LisperExp::Int(1));
+
+        Ok(())
+    }
+}
@@ -1,13 +1,189 @@
+use std::collections::HashMap;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::core::LisperErr;
+use crate::env::LisperEnv;
+use crate::num::{ BigInt, Ratio };
 // Represents an individual Lisp expresion
 #[derive(Clone)]
 pub enum LisperExp {
     Bool(bool),
     Symbol(String),
+    // A name that evaluates to itself, written :name, for tags and map keys
+    Keyword(String),
+    // Numbers are exact integers, which become big integers when they overflow an i64,
+    // exact fractions, or floats
+    Int(i64),
+    BigInt(BigInt),
+    Ratio(Ratio),
     Number(f64),
-    List(Vec<LisperExp>),
-    Func(fn(&LisperExp) -> LisperExp),
-    Lambda(Vec<LisperExp>),
+    Str(String),
+    // Lists read from source carry the span of code they were parsed from
+    List(Vec<LisperExp>, Option<Span>),
+    // A hash map from any value to another, written {:key value ...}. Functions used as
+    // keys hold an environment that can change, but they hash and compare by pointer,
+    // so that never changes their key.
+    #[allow(clippy::mutable_key_type)]
+    Map(HashMap<LisperExp, LisperExp>),
+    // A map literal as read from source, with its key and value expressions in the order
+    // they were written. Evaluating it evaluates them left to right, and builds the map.
+    MapLiteral(Vec<(LisperExp, LisperExp)>),
+    // A sequence of values indexed in constant time, written [1 2 3]
+    Vector(Vec<LisperExp>),
+    Func(Rc<LisperNative>),
+    Lambda(Rc<LisperLambda>),
+    // A macro is a function from code to code, run on its unevaluated arguments
+    Macro(Rc<LisperLambda>),
+}
+
+// The signature of functions implemented in Rust. They get the evaluated arguments, and
+// the environment of the call so they can call back into Lisper values.
+pub type NativeFn = dyn Fn(&[LisperExp], &mut LisperEnv) -> Result<LisperExp, LisperErr>;
+
+// A function implemented in Rust, either a builtin or one registered by the host application
+pub struct LisperNative {
+    pub name: String,
+    // The fewest arguments it takes, and the most, if there is a limit
+    pub min_args: usize,
+    pub max_args: Option<usize>,
+    pub func: Rc<NativeFn>,
+}
+
+impl LisperNative {
+    // Calls the function, after checking it was given as many arguments as it takes
+    pub fn call(&self, args: &[LisperExp], env: &mut LisperEnv) -> Result<LisperExp, LisperErr> {
+        let got = args.len();
+        if got < self.min_args || self.max_args.is_some_and(|max| got > max) {
+            return Err(LisperErr::arity(&self.name, self.min_args, self.max_args, got));
+        }
+        (self.func)(args, env)
+    }
+}
+
+// A user defined function, which closes over the environment it was created in
+#[derive(Clone)]
+pub struct LisperLambda {
+    // The name it was defined with, anonymous lambdas don't have one
+    pub name: Option<String>,
+    pub params: LisperParams,
+    pub body: Vec<LisperExp>,
+    pub env: LisperEnv,
+}
+
+// The parameters of a user defined function. Required parameters come first, then
+// &optional ones with default expressions, a &rest list of any extra arguments, and
+// &key parameters passed by name, e.g. (f 1 :width 10).
+#[derive(Clone, Default)]
+pub struct LisperParams {
+    pub required: Vec<String>,
+    pub optional: Vec<(String, LisperExp)>,
+    pub rest: Option<String>,
+    pub keys: Vec<(String, LisperExp)>,
+}
+
+impl LisperParams {
+    // Lists the parameters the way they are written in source
+    pub fn spec(&self) -> Vec<String> {
+        let with_default = |(name, default): &(String, LisperExp)| format!("({} {})", name, default);
+        let mut spec: Vec<String> = self.required.to_vec();
+        if !self.optional.is_empty() {
+            spec.push("&optional".to_string());
+            spec.extend(self.optional.iter().map(with_default));
+        }
+        if let Some(rest) = &self.rest {
+            spec.push("&rest".to_string());
+            spec.push(rest.to_string());
+        }
+        if !self.keys.is_empty() {
+            spec.push("&key".to_string());
+            spec.extend(self.keys.iter().map(with_default));
+        }
+        spec
+    }
+}
+
+// A range of source code, as byte offsets plus the line and column it starts on
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    // Creates a span covering everything from the start of this span to the end of the other
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end, ..self }
+    }
+}
+
+// Compares values structurally. Spans are ignored, and functions are only equal to themselves.
+impl PartialEq for LisperExp {
+    fn eq(&self, other: &LisperExp) -> bool {
+        match (self, other) {
+            (LisperExp::Bool(a), LisperExp::Bool(b)) => a == b,
+            (LisperExp::Symbol(a), LisperExp::Symbol(b)) => a == b,
+            (LisperExp::Keyword(a), LisperExp::Keyword(b)) => a == b,
+            (LisperExp::Int(a), LisperExp::Int(b)) => a == b,
+            (LisperExp::BigInt(a), LisperExp::BigInt(b)) => a == b,
+            (LisperExp::Ratio(a), LisperExp::Ratio(b)) => a == b,
+            (LisperExp::Number(a), LisperExp::Number(b)) => a == b,
+            (LisperExp::Str(a), LisperExp::Str(b)) => a == b,
+            (LisperExp::List(a, _), LisperExp::List(b, _)) => a == b,
+            (LisperExp::Map(a), LisperExp::Map(b)) => a == b,
+            (LisperExp::MapLiteral(a), LisperExp::MapLiteral(b)) => a == b,
+            (LisperExp::Vector(a), LisperExp::Vector(b)) => a == b,
+            (LisperExp::Func(a), LisperExp::Func(b)) => Rc::ptr_eq(a, b),
+            (LisperExp::Lambda(a), LisperExp::Lambda(b)) => Rc::ptr_eq(a, b),
+            (LisperExp::Macro(a), LisperExp::Macro(b)) => Rc::ptr_eq(a, b),
+            _ => false
+        }
+    }
+}
+
+// Values are used as map keys. NaN is the one value not equal to itself, so it can be stored
+// as a key but never found again.
+impl Eq for LisperExp {}
+
+// Hashes values consistently with the structural equality above
+impl Hash for LisperExp {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            LisperExp::Bool(b) => b.hash(state),
+            LisperExp::Symbol(s) | LisperExp::Keyword(s) | LisperExp::Str(s) => s.hash(state),
+            LisperExp::Int(n) => n.hash(state),
+            LisperExp::BigInt(n) => n.hash(state),
+            LisperExp::Ratio(n) => n.hash(state),
+            // 0.0 and -0.0 are equal, so have to hash the same
+            LisperExp::Number(n) => (if *n == 0.0 { 0.0 } else { *n }).to_bits().hash(state),
+            LisperExp::List(list, _) | LisperExp::Vector(list) => list.hash(state),
+            // Entries have no order, so only the size goes into the hash
+            LisperExp::Map(map) => map.len().hash(state),
+            LisperExp::MapLiteral(pairs) => pairs.hash(state),
+            LisperExp::Func(func) => Rc::as_ptr(func).hash(state),
+            LisperExp::Lambda(lambda) | LisperExp::Macro(lambda) => Rc::as_ptr(lambda).hash(state),
+        }
+    }
+}
+
+// Lists the entries of a map in a stable order, sorted by how the keys are printed, which
+// is the order maps are printed and iterated in
+#[allow(clippy::mutable_key_type)]
+pub fn map_entries(map: &HashMap<LisperExp, LisperExp>) -> Vec<(&LisperExp, &LisperExp)> {
+    let mut entries: Vec<(String, (&LisperExp, &LisperExp))> = map.iter().map(|entry| (entry.0.to_string(), entry)).collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+// Shown the same way as Display, so errors carrying values can derive Debug
+impl fmt::Debug for LisperExp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
 }
 
 // Used for to_string
@@ -15,15 +191,59 @@ impl fmt::Display for LisperExp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let str:String = match self {
             LisperExp::Symbol(s) => s.to_string(),
+            LisperExp::Keyword(name) => format!(":{}", name),
+            LisperExp::Int(n) => n.to_string(),
+            LisperExp::BigInt(n) => n.to_string(),
+            LisperExp::Ratio(n) => n.to_string(),
+            LisperExp::Number(n) if n.is_finite() && n.fract() == 0.0 => {
+                // Floats keep a decimal point, so they aren't mistaken for integers
+                format!("{}.0", n)
+            },
             LisperExp::Number(n) => n.to_string(),
             LisperExp::Bool(b) => b.to_string(),
-            LisperExp::List(list) | LisperExp::Lambda(list) => {
+            LisperExp::Str(s) => {
+                // Print strings the way they would be written in source
+                let mut escaped = String::from("\"");
+                for c in s.chars() {
+                    match c {
+                        '"' => escaped.push_str("\\\""),
+                        '\\' => escaped.push_str("\\\\"),
+                        '\n' => escaped.push_str("\\n"),
+                        '\t' => escaped.push_str("\\t"),
+                        c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                        c => escaped.push(c),
+                    }
+                }
+                escaped.push('"');
+                escaped
+            },
+            LisperExp::List(list, _) => {
+                // Lists print the way they are written, e.g. (1 (2 3) "four")
                 let items:Vec<String> = list.iter().map(|item| item.to_string()).collect();
-                format!("({})", items.join(","))
+                format!("({})", items.join(" "))
+            },
+            LisperExp::Map(map) => {
+                // Maps print as the literal that reads back as them, e.g. {:a 1 :b (2 3)}
+                let items:Vec<String> = map_entries(map).iter().map(|(key, value)| format!("{} {}", key, value)).collect();
+                format!("{{{}}}", items.join(" "))
             },
-            LisperExp::Func(_) => "Function".to_string()
+            LisperExp::MapLiteral(pairs) => {
+                let items:Vec<String> = pairs.iter().map(|(key, value)| format!("{} {}", key, value)).collect();
+                format!("{{{}}}", items.join(" "))
+            },
+            LisperExp::Vector(items) => {
+                let items:Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                format!("[{}]", items.join(" "))
+            },
+            LisperExp::Lambda(lambda) | LisperExp::Macro(lambda) => {
+                let form = if matches!(self, LisperExp::Macro(_)) { "macro" } else { "lambda" };
+                let mut items:Vec<String> = vec![form.to_string(), format!("({})", lambda.params.spec().join(" "))];
+                items.extend(lambda.body.iter().map(|exp| exp.to_string()));
+                format!("({})", items.join(" "))
+            },
+            LisperExp::Func(func) => func.name.clone()
         };
-        
+
         write!(f, "{}", str)
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,4 @@
 pub mod exp;
 pub mod env;
-pub mod core;
\ No newline at end of file
+pub mod core;
+pub mod num;
@@ -0,0 +1,583 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::convert::TryFrom;
+
+use crate::exp::LisperExp;
+
+// Each digit of a big integer holds this many decimal digits, so printing and parsing
+// them is just splitting the decimal string up
+const BASE: u64 = 1_000_000_000;
+const BASE_DIGITS: usize = 9;
+
+// An integer of any size, for results that don't fit in an i64. The magnitude is stored
+// as base 10^9 digits, least significant first, with no leading zeros, so zero has no
+// digits and is never negative.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    digits: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn from_i64(n: i64) -> BigInt {
+        let mut magnitude = n.unsigned_abs();
+        let mut digits: Vec<u32> = vec![];
+        while magnitude > 0 {
+            digits.push((magnitude % BASE) as u32);
+            magnitude /= BASE;
+        }
+        BigInt { negative: n < 0, digits }
+    }
+
+    // Reads a decimal integer, with an optional sign, e.g. -123456789012345678901234
+    pub fn parse(s: &str) -> Option<BigInt> {
+        let (negative, decimal) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s))
+        };
+        if decimal.is_empty() || !decimal.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let digits: Vec<u32> = decimal.as_bytes().rchunks(BASE_DIGITS)
+            .map(|chunk| chunk.iter().fold(0, |digit, b| digit * 10 + (b - b'0') as u32))
+            .collect();
+        Some(BigInt::new(negative, digits))
+    }
+
+    // Creates a big integer from digits that may have leading zeros
+    fn new(negative: bool, mut digits: Vec<u32>) -> BigInt {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        BigInt { negative: negative && !digits.is_empty(), digits }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    // Gives the value as an i64, if it fits in one
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 3 {
+            return None;
+        }
+        let magnitude = self.digits.iter().rev().fold(0i128, |n, digit| n * BASE as i128 + *digit as i128);
+        i64::try_from(if self.negative { -magnitude } else { magnitude }).ok()
+    }
+
+    // Gives the nearest float, which is infinite for integers too big for one
+    pub fn to_f64(&self) -> f64 {
+        // Parsing the decimal form rounds correctly, where adding up the digits wouldn't
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    pub fn neg(&self) -> BigInt {
+        BigInt::new(!self.negative, self.digits.clone())
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::new(false, self.digits.clone())
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_digits(&self.digits, &other.digits));
+        }
+        // Signs differ, so take the smaller magnitude from the larger one
+        match cmp_digits(&self.digits, &other.digits) {
+            Ordering::Less => BigInt::new(other.negative, sub_digits(&other.digits, &self.digits)),
+            _ => BigInt::new(self.negative, sub_digits(&self.digits, &other.digits)),
+        }
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        let mut product: Vec<u64> = vec![0; self.digits.len() + other.digits.len()];
+        for (i, a) in self.digits.iter().enumerate() {
+            let mut carry = 0;
+            for (j, b) in other.digits.iter().enumerate() {
+                let total = product[i + j] + *a as u64 * *b as u64 + carry;
+                product[i + j] = total % BASE;
+                carry = total / BASE;
+            }
+            product[i + other.digits.len()] += carry;
+        }
+        BigInt::new(self.negative != other.negative, product.into_iter().map(|digit| digit as u32).collect())
+    }
+
+    // Divides, rounding the quotient towards zero, so the remainder has the sign of self.
+    // The divisor can't be zero.
+    pub fn div_rem(&self, other: &BigInt) -> (BigInt, BigInt) {
+        let mut quotient: Vec<u32> = vec![0; self.digits.len()];
+        let mut remainder = BigInt::default();
+        for (i, digit) in self.digits.iter().enumerate().rev() {
+            // Bring down the next digit, then find the largest quotient digit that fits
+            remainder.digits.insert(0, *digit);
+            remainder = BigInt::new(false, remainder.digits);
+            let (mut low, mut high) = (0, BASE as u32 - 1);
+            while low < high {
+                let mid = low + (high - low).div_ceil(2);
+                if cmp_digits(&mul_digit(&other.digits, mid), &remainder.digits) == Ordering::Greater {
+                    high = mid - 1;
+                } else {
+                    low = mid;
+                }
+            }
+            quotient[i] = low;
+            remainder = BigInt::new(false, sub_digits(&remainder.digits, &mul_digit(&other.digits, low)));
+        }
+        (BigInt::new(self.negative != other.negative, quotient), BigInt::new(self.negative, remainder.digits))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_digits(&self.digits, &other.digits),
+            (true, true) => cmp_digits(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut decimal = String::from(if self.negative { "-" } else { "" });
+        match self.digits.split_last() {
+            Some((top, rest)) => {
+                decimal.push_str(&top.to_string());
+                for digit in rest.iter().rev() {
+                    decimal.push_str(&format!("{:09}", digit));
+                }
+            },
+            None => decimal.push('0')
+        }
+        write!(f, "{}", decimal)
+    }
+}
+
+// Compares the magnitudes of two numbers
+fn cmp_digits(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut sum: Vec<u32> = vec![];
+    let mut carry = 0;
+    for i in 0..a.len().max(b.len()) {
+        let total = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        sum.push((total % BASE) as u32);
+        carry = total / BASE;
+    }
+    if carry > 0 {
+        sum.push(carry as u32);
+    }
+    sum
+}
+
+// Takes b from a, where a is at least as big as b
+fn sub_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference: Vec<u32> = vec![];
+    let mut borrow = 0;
+    for (i, digit) in a.iter().enumerate() {
+        let take = *b.get(i).unwrap_or(&0) as i64 + borrow;
+        let mut total = *digit as i64 - take;
+        borrow = 0;
+        if total < 0 {
+            total += BASE as i64;
+            borrow = 1;
+        }
+        difference.push(total as u32);
+    }
+    while difference.last() == Some(&0) {
+        difference.pop();
+    }
+    difference
+}
+
+fn mul_digit(a: &[u32], digit: u32) -> Vec<u32> {
+    let mut product: Vec<u32> = vec![];
+    let mut carry = 0;
+    for d in a {
+        let total = *d as u64 * digit as u64 + carry;
+        product.push((total % BASE) as u32);
+        carry = total / BASE;
+    }
+    if carry > 0 {
+        product.push(carry as u32);
+    }
+    while product.last() == Some(&0) {
+        product.pop();
+    }
+    product
+}
+
+// Finds the greatest common divisor of two integers, which is never negative
+fn gcd(a: &BigInt, b: &BigInt) -> BigInt {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while !b.is_zero() {
+        let remainder = a.div_rem(&b).1;
+        a = b;
+        b = remainder;
+    }
+    a
+}
+
+// An exact fraction, e.g. 1/3. Made through Num::fraction, which keeps it in lowest terms with
+// a positive denominator, and gives an integer instead when the denominator would be 1.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Ratio {
+    numer: BigInt,
+    denom: BigInt,
+}
+
+impl Ratio {
+    // Reads a fraction literal, e.g. -2/3, as its numerator and denominator, which can be
+    // zero or not in lowest terms
+    pub fn parse(s: &str) -> Option<(BigInt, BigInt)> {
+        let (numer, denom) = s.split_once('/')?;
+        if denom.starts_with(['-', '+']) {
+            return None;
+        }
+        Some((BigInt::parse(numer)?, BigInt::parse(denom)?))
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.numer
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.denom
+    }
+
+    fn from_big(n: BigInt) -> Ratio {
+        Ratio { numer: n, denom: BigInt::from_i64(1) }
+    }
+
+    // Gives the nearest float. Converting the parts separately would overflow to inf/inf for
+    // big ones, so this divides exactly first, keeping about 20 significant digits, and then
+    // parses the decimal form, which rounds correctly.
+    pub fn to_f64(&self) -> f64 {
+        let digits = |n: &BigInt| n.abs().to_string().len() as i64;
+        let power = |n: i64| BigInt::parse(&format!("1{}", "0".repeat(n as usize))).unwrap_or_default();
+        let shift = digits(&self.denom) - digits(&self.numer) + 20;
+        let (quotient, remainder) = if shift >= 0 {
+            self.numer.abs().mul(&power(shift)).div_rem(&self.denom.abs())
+        } else {
+            self.numer.abs().div_rem(&self.denom.abs().mul(&power(-shift)))
+        };
+        // A digit for anything left over, so values halfway between two floats round the right way
+        let (sticky, shift) = if remainder.is_zero() { ("", shift) } else { ("1", shift + 1) };
+        let sign = if self.numer.is_negative() { "-" } else { "" };
+        format!("{}{}{}e{}", sign, quotient, sticky, -shift).parse().unwrap_or(f64::NAN)
+    }
+
+    fn add(&self, other: &Ratio) -> Num {
+        Num::fraction(self.numer.mul(&other.denom).add(&other.numer.mul(&self.denom)), self.denom.mul(&other.denom))
+    }
+
+    fn sub(&self, other: &Ratio) -> Num {
+        Num::fraction(self.numer.mul(&other.denom).sub(&other.numer.mul(&self.denom)), self.denom.mul(&other.denom))
+    }
+
+    fn mul(&self, other: &Ratio) -> Num {
+        Num::fraction(self.numer.mul(&other.numer), self.denom.mul(&other.denom))
+    }
+
+    fn div(&self, other: &Ratio) -> Num {
+        Num::fraction(self.numer.mul(&other.denom), self.denom.mul(&other.numer))
+    }
+
+    // Divides, rounding towards zero
+    fn quot(&self, other: &Ratio) -> Num {
+        Num::Big(self.numer.mul(&other.denom).div_rem(&self.denom.mul(&other.numer)).0)
+    }
+
+    fn rem(&self, other: &Ratio) -> Num {
+        match self.quot(other) {
+            Num::Big(quotient) => self.sub(&other.mul(&Ratio::from_big(quotient)).to_ratio()),
+            _ => Num::Int(0)
+        }
+    }
+
+    fn cmp(&self, other: &Ratio) -> Ordering {
+        // Denominators are positive, so cross multiplying keeps the order
+        self.numer.mul(&other.denom).cmp(&other.numer.mul(&self.denom))
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.numer, self.denom)
+    }
+}
+
+// A number taken out of a LisperExp to do arithmetic on. Integers and fractions stay exact,
+// integers moving to big integers when they overflow, and any float makes the result a float.
+#[derive(Clone)]
+pub enum Num {
+    Int(i64),
+    Big(BigInt),
+    Ratio(Ratio),
+    Float(f64),
+}
+
+impl Num {
+    // Creates the number numer/denom, where denom isn't zero
+    pub fn fraction(numer: BigInt, denom: BigInt) -> Num {
+        let divisor = gcd(&numer, &denom);
+        let (mut numer, mut denom) = (numer.div_rem(&divisor).0, denom.div_rem(&divisor).0);
+        if denom.is_negative() {
+            numer = numer.neg();
+            denom = denom.neg();
+        }
+        if denom == BigInt::from_i64(1) {
+            Num::Big(numer)
+        } else {
+            Num::Ratio(Ratio { numer, denom })
+        }
+    }
+
+    pub fn from_exp(exp: &LisperExp) -> Option<Num> {
+        match exp {
+            LisperExp::Int(n) => Some(Num::Int(*n)),
+            LisperExp::BigInt(n) => Some(Num::Big(n.clone())),
+            LisperExp::Ratio(n) => Some(Num::Ratio(n.clone())),
+            LisperExp::Number(n) => Some(Num::Float(*n)),
+            _ => None
+        }
+    }
+
+    // Puts the number back in a LisperExp, as an i64 if it fits in one
+    pub fn into_exp(self) -> LisperExp {
+        match self {
+            Num::Int(n) => LisperExp::Int(n),
+            Num::Big(n) => match n.to_i64() {
+                Some(n) => LisperExp::Int(n),
+                None => LisperExp::BigInt(n)
+            },
+            Num::Ratio(n) => LisperExp::Ratio(n),
+            Num::Float(n) => LisperExp::Number(n),
+        }
+    }
+
+    // Gives the exact fraction a float prints as, e.g. 0.1 is 1/10, or None for infinity
+    // and NaN. Integers and fractions are already exact.
+    pub fn to_exact(&self) -> Option<Num> {
+        let Num::Float(n) = self else {
+            return Some(self.clone());
+        };
+        if !n.is_finite() {
+            return None;
+        }
+        // Floats print as plain decimals, without an exponent
+        let decimal = n.to_string();
+        let (whole, fraction) = decimal.split_once('.').unwrap_or((&decimal, ""));
+        let numer = BigInt::parse(&format!("{}{}", whole, fraction))?;
+        let denom = BigInt::parse(&format!("1{}", "0".repeat(fraction.len())))?;
+        Some(Num::fraction(numer, denom))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Num::Int(n) => *n as f64,
+            Num::Big(n) => n.to_f64(),
+            Num::Ratio(n) => n.to_f64(),
+            Num::Float(n) => *n,
+        }
+    }
+
+    // Gives an exact number as a fraction, integers having a denominator of 1
+    fn to_ratio(&self) -> Ratio {
+        match self {
+            Num::Int(n) => Ratio::from_big(BigInt::from_i64(*n)),
+            Num::Big(n) => Ratio::from_big(n.clone()),
+            Num::Ratio(n) => n.clone(),
+            // Only called on exact numbers
+            Num::Float(_) => Ratio::from_big(BigInt::default()),
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Num::Float(_))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Num::Int(n) => *n == 0,
+            Num::Big(n) => n.is_zero(),
+            Num::Ratio(_) => false,
+            Num::Float(n) => *n == 0.0,
+        }
+    }
+
+    // Applies an operation to two numbers, as floats if either is one, with i64s if the
+    // result fits, and otherwise as exact fractions
+    fn combine(&self, other: &Num, float: fn(f64, f64) -> f64, int: fn(i64, i64) -> Option<i64>, exact: fn(&Ratio, &Ratio) -> Num) -> Num {
+        match (self, other) {
+            (Num::Float(_), _) | (_, Num::Float(_)) => Num::Float(float(self.to_f64(), other.to_f64())),
+            (Num::Int(a), Num::Int(b)) => match int(*a, *b) {
+                Some(n) => Num::Int(n),
+                None => exact(&self.to_ratio(), &other.to_ratio())
+            },
+            _ => exact(&self.to_ratio(), &other.to_ratio())
+        }
+    }
+
+    pub fn add(&self, other: &Num) -> Num {
+        self.combine(other, |a, b| a + b, i64::checked_add, Ratio::add)
+    }
+
+    pub fn sub(&self, other: &Num) -> Num {
+        self.combine(other, |a, b| a - b, i64::checked_sub, Ratio::sub)
+    }
+
+    pub fn mul(&self, other: &Num) -> Num {
+        self.combine(other, |a, b| a * b, i64::checked_mul, Ratio::mul)
+    }
+
+    // Divides, giving an exact fraction for exact numbers, e.g. (/ 1 3) is 1/3. The divisor
+    // can't be zero.
+    pub fn div(&self, other: &Num) -> Num {
+        let exact_div = |a: i64, b: i64| if a.checked_rem(b) == Some(0) { a.checked_div(b) } else { None };
+        self.combine(other, |a, b| a / b, exact_div, Ratio::div)
+    }
+
+    // Divides, rounding towards zero. The divisor can't be zero.
+    pub fn quot(&self, other: &Num) -> Num {
+        self.combine(other, |a, b| (a / b).trunc(), i64::checked_div, Ratio::quot)
+    }
+
+    // The remainder of dividing, which has the sign of the number divided. The divisor
+    // can't be zero.
+    pub fn rem(&self, other: &Num) -> Num {
+        self.combine(other, |a, b| a % b, i64::checked_rem, Ratio::rem)
+    }
+
+    pub fn neg(&self) -> Num {
+        Num::Int(0).sub(self)
+    }
+
+    // Rounds down to an integer
+    pub fn floor(&self) -> Num {
+        let truncated = self.quot(&Num::Int(1));
+        if self.partial_cmp(&truncated) == Some(Ordering::Less) {
+            truncated.sub(&Num::Int(1))
+        } else {
+            truncated
+        }
+    }
+
+    // Compares numbers by value, whatever their type. NaN isn't ordered.
+    pub fn partial_cmp(&self, other: &Num) -> Option<Ordering> {
+        match (self, other) {
+            (Num::Float(_), _) | (_, Num::Float(_)) => self.to_f64().partial_cmp(&other.to_f64()),
+            (Num::Int(a), Num::Int(b)) => Some(a.cmp(b)),
+            _ => Some(self.to_ratio().cmp(&other.to_ratio()))
+        }
+    }
+}
+
+// Finds the simplest fraction, the one with the smallest denominator, between two exact
+// numbers, where low is at most high
+pub fn simplest_between(low: &Num, high: &Num) -> Num {
+    let zero = Num::Int(0);
+    if high.partial_cmp(&zero) == Some(Ordering::Less) {
+        return simplest_between(&high.neg(), &low.neg()).neg();
+    }
+    if low.partial_cmp(&zero) != Some(Ordering::Greater) {
+        return zero;
+    }
+    // Both are positive, so take the whole part, and continue with the reciprocal of the rest
+    let whole = low.floor();
+    if whole.partial_cmp(low) == Some(Ordering::Equal) {
+        whole
+    } else if whole.partial_cmp(&high.floor()) == Some(Ordering::Less) {
+        whole.add(&Num::Int(1))
+    } else {
+        let rest = simplest_between(&Num::Int(1).div(&high.sub(&whole)), &Num::Int(1).div(&low.sub(&whole)));
+        whole.add(&Num::Int(1).div(&rest))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
+mod tests {
+    #[test]
+    fn big_int_arithmetic() {
+        use super::*;
+
+        let big = |s: &str| BigInt::parse(s).unwrap();
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432");
+        assert_eq!(a.add(&b).to_string(), "123456789011358024580135802458");
+        assert_eq!(a.sub(&b).to_string(), "123456789013333333222333333322");
+        assert_eq!(a.mul(&b).to_string(), "-121932631137021795212620027521140070120989178480");
+        let (quotient, remainder) = a.div_rem(&b);
+        assert_eq!(quotient.to_string(), "-124999998860");
+        assert_eq!(remainder.to_string(), "925925953827160370");
+        assert!(quotient.mul(&b).add(&remainder) == a);
+
+        assert_eq!(big("-000").to_string(), "0");
+        assert_eq!(big("1000000000").to_string(), "1000000000");
+        assert_eq!(BigInt::from_i64(i64::MIN).to_string(), "-9223372036854775808");
+        assert_eq!(BigInt::from_i64(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert!(big("-5") < big("3") && big("-5") < big("-4"));
+        assert!(BigInt::parse("12a").is_none() && BigInt::parse("-").is_none());
+    }
+
+    #[test]
+    fn num_contagion() {
+        use super::*;
+
+        // Exact integers overflow into big integers, and back again
+        let max = Num::Int(i64::MAX);
+        let over = max.add(&Num::Int(1)).into_exp();
+        assert_eq!(over.to_string(), "9223372036854775808");
+        let back = Num::from_exp(&over).unwrap().sub(&Num::Int(1)).into_exp();
+        assert!(back == LisperExp::Int(i64::MAX));
+        assert_eq!(Num::Int(i64::MIN).neg().into_exp().to_string(), "9223372036854775808");
+
+        // Floats win, and dividing exact numbers stays exact
+        assert!(Num::Int(1).add(&Num::Float(0.5)).into_exp() == LisperExp::Number(1.5));
+        assert!(Num::Int(6).div(&Num::Int(3)).into_exp() == LisperExp::Int(2));
+        assert_eq!(Num::Int(1).div(&Num::Int(4)).into_exp().to_string(), "1/4");
+        assert!(Num::Int(1).div(&Num::Float(4.0)).into_exp() == LisperExp::Number(0.25));
+        assert!(Num::Int(-7).quot(&Num::Int(2)).into_exp() == LisperExp::Int(-3));
+        assert!(Num::Int(-7).rem(&Num::Int(2)).into_exp() == LisperExp::Int(-1));
+        assert!(Num::Int(i64::MIN).quot(&Num::Int(-1)).into_exp() == LisperExp::BigInt(BigInt::parse("9223372036854775808").unwrap()));
+        assert_eq!(Num::Int(1).partial_cmp(&Num::Float(1.0)), Some(Ordering::Equal));
+    }
+
+    #[test]
+    fn ratio_to_f64() {
+        use super::*;
+
+        let fraction = |numer: &str, denom: &str| Num::fraction(BigInt::parse(numer).unwrap(), BigInt::parse(denom).unwrap());
+        assert_eq!(fraction("1", "3").to_f64(), 1.0 / 3.0);
+        assert_eq!(fraction("-7", "2").to_f64(), -3.5);
+        // Parts above 1e308 divide before converting, rather than giving inf/inf
+        let huge = format!("3{}", "0".repeat(320));
+        assert_eq!(fraction(&format!("{}1", huge), &huge).to_f64(), 10.0);
+        assert_eq!(fraction("1", &format!("{}{}", huge, huge)).to_f64(), 0.0);
+        assert_eq!(fraction(&huge, "7").to_f64(), f64::INFINITY);
+        // and round to the nearest float, breaking ties to even
+        assert_eq!(fraction("9007199254740993", "1").to_f64(), 9007199254740992.0);
+        assert_eq!(fraction("9007199254740993000000000000000000000001", "1000000000000000000000000").to_f64(), 9007199254740994.0);
+    }
+}
@@ -1,11 +1,11 @@
-use std::{ str::Lines };
 use wasm_bindgen::prelude::*;
 
 use lisper::env::{ LisperEnv, create_default_env };
 use lisper::core::{ 
     tokenize,
-    parse,
-    eval,
+    parse_program,
+    eval_program,
+    Token,
     LisperErr
 };
 
@@ -14,32 +14,19 @@ pub fn run(exp: String) -> String {
     // Create lisper environment
     let env = &mut create_default_env();
     
-    // Split lines into strings and evaluate as lisper expressions
-    let lines: Lines = exp.lines();
-    match evaluate_lines(lines, env) {
+    // Evaluate every expression in the program, and show the last result
+    match evaluate(&exp, env) {
         Ok(res) => res,
-        Err(e) => e.to_string(),
+        Err(e) => e.render(&exp),
     }
 }
 
-fn evaluate_lines(exp_lines:Lines, env: &mut LisperEnv) -> Result<String, LisperErr> {
-    let results = exp_lines
-            .map(|l| {
-                match evaluate(l.to_string(), env) {
-                    Ok(res) => res,
-                    Err(e) => e.to_string()
-                }
-            })
-            .collect::<Vec<String>>();
-    Ok(results.last().unwrap().to_string())
-}
-
-fn evaluate(exp:String, env: &mut LisperEnv) -> Result<String, LisperErr> {
-    let tokens:Vec<String> = tokenize(exp);
-    let (parsed_tokens, _) = parse(&tokens)?;
-    let eval_out = eval(parsed_tokens, env)?;
+fn evaluate(exp: &str, env: &mut LisperEnv) -> Result<String, LisperErr> {
+    let tokens:Vec<Token> = tokenize(exp.to_string());
+    let program = parse_program(&tokens)?;
+    let eval_out = eval_program(program, env)?;
 
-    Ok(eval_out.to_string())
+    Ok(eval_out.last().map(|res| res.to_string()).unwrap_or_default())
 }
 
 #[cfg(test)]
@@ -57,4 +44,32 @@ mod tests {
         let actual_result:String = super::run("(def w 2)\n(+ 2 w)".to_string());
         assert_eq!(actual_result, expected_result);
     }
+
+    #[test]
+    fn multiple_exp_per_line() {
+        let expected_result:String = "3".to_string();
+        let actual_result:String = super::run("(def a 1) (def b 2) (+ a b)".to_string());
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test]
+    fn exp_spanning_lines() {
+        let expected_result:String = "6".to_string();
+        let actual_result:String = super::run("(def w\n  2)\n(* w\n   3)".to_string());
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test]
+    fn comment_exp() {
+        let expected_result:String = "4".to_string();
+        let actual_result:String = super::run("; setup\n(def w 2) ; two\n(+ 2 w)\n; done".to_string());
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test]
+    fn error_exp() {
+        let expected_result:String = "Error = Unbound variable `foo`. (line 1, column 6)\n1 | (+ 1 (foo 2))\n  |      ^^^^^^^".to_string();
+        let actual_result:String = super::run("(+ 1 (foo 2))".to_string());
+        assert_eq!(actual_result, expected_result);
+    }
 }